@@ -0,0 +1,130 @@
+use crate::executor::ActivityWakeHandle;
+use crate::executor::Executor;
+use crate::listen_socket::ListenSocket;
+use crate::types::*;
+use crate::unix_listen_socket::UnixListenSocket;
+use nix::errno::Errno;
+use nix::sys::socket::SockAddr;
+use std::collections::VecDeque;
+use std::future::ready;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+/// Listening socket that `accept` takes connections from.
+pub trait ListeningSocket {
+    fn fd(&self) -> RawFd;
+
+    /// Accepts a connection, returning its non-blocking socket and the address of the peer.
+    fn accept(&mut self) -> nix::Result<(RawFd, SockAddr)>;
+}
+
+impl ListeningSocket for ListenSocket {
+    fn fd(&self) -> RawFd {
+        ListenSocket::fd(self)
+    }
+
+    fn accept(&mut self) -> nix::Result<(RawFd, SockAddr)> {
+        ListenSocket::accept(self)
+    }
+}
+
+impl ListeningSocket for UnixListenSocket {
+    fn fd(&self) -> RawFd {
+        UnixListenSocket::fd(self)
+    }
+
+    fn accept(&mut self) -> nix::Result<(RawFd, SockAddr)> {
+        UnixListenSocket::accept(self)
+    }
+}
+
+/// Listener that can be turned into a stream of connections with `Incoming`.
+pub trait Listener {
+    type Connection;
+
+    fn accept(&mut self) -> Box<dyn Future<Output = BoxResult<Self::Connection>> + Unpin + '_>;
+}
+
+/// Waits for a connection on `listen_socket`. Every connection that is pending is accepted at
+/// once, turned into a stream by `connection` and queued in `queue`, from which the first one
+/// is returned.
+pub fn accept<'a, S, C, F>(
+    listen_socket: &'a mut S,
+    queue: &'a mut VecDeque<C>,
+    connection: F,
+) -> Box<dyn Future<Output = BoxResult<C>> + Unpin + 'a>
+where
+    S: ListeningSocket,
+    C: 'a,
+    F: Fn(RawFd, SockAddr) -> BoxResult<C> + Unpin + 'a,
+{
+    match Executor::current().wake_on_activity(listen_socket.fd(), Operation::READ) {
+        Ok(activity_wake_handle) => Box::new(AcceptFuture {
+            listen_socket,
+            queue,
+            connection,
+            activity_wake_handle,
+        }),
+        Err(err) => Box::new(ready(Err(err))),
+    }
+}
+
+struct AcceptFuture<'a, S, C, F> {
+    listen_socket: &'a mut S,
+    queue: &'a mut VecDeque<C>,
+    connection: F,
+    activity_wake_handle: ActivityWakeHandle,
+}
+
+impl<'a, S, C, F> Future for AcceptFuture<'a, S, C, F>
+where
+    S: ListeningSocket,
+    F: Fn(RawFd, SockAddr) -> BoxResult<C> + Unpin,
+{
+    type Output = BoxResult<C>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            match self.listen_socket.accept() {
+                Ok((socket, sock_addr)) => match (self.connection)(socket, sock_addr) {
+                    Ok(connection) => self.queue.push_back(connection),
+                    Err(err) => return Poll::Ready(Err(err)),
+                },
+                Err(nix::Error::Sys(Errno::EAGAIN)) => {
+                    self.activity_wake_handle.set_waker(cx.waker());
+                    break;
+                }
+                Err(err) => return Poll::Ready(Err(Box::new(err))),
+            }
+        }
+        match self.queue.pop_front() {
+            None => Poll::Pending,
+            Some(connection) => Poll::Ready(Ok(connection)),
+        }
+    }
+}
+
+/// Never-ending stream of the connections accepted by a listener.
+pub struct Incoming<'a, L> {
+    listener: &'a mut L,
+}
+
+impl<'a, L> Incoming<'a, L> {
+    pub fn new(listener: &'a mut L) -> Incoming<'a, L> {
+        Incoming { listener }
+    }
+}
+
+impl<'a, L> Stream for Incoming<'a, L>
+where
+    L: Listener,
+{
+    type Item = BoxResult<L::Connection>;
+
+    fn next(&mut self) -> Box<dyn Future<Output = Option<Self::Item>> + Unpin + '_> {
+        let accept = self.listener.accept();
+        Box::new(Box::pin(async move { Some(accept.await) }))
+    }
+}
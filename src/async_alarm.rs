@@ -0,0 +1,136 @@
+use crate::executor::ActivityWakeHandle;
+use crate::executor::Executor;
+use crate::types::*;
+use nix::errno::Errno;
+use nix::unistd::close;
+use nix::unistd::read;
+use std::future::Future;
+use std::pin::Pin;
+use std::ptr;
+use std::task::Context;
+use std::task::Poll;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Not exported by `libc` yet, see `timerfd_settime(2)`.
+const TFD_TIMER_CANCEL_ON_SET: libc::c_int = 1 << 1;
+
+/// Creates an `Alarm` that completes once the system clock reaches `time`. Unlike the timers of
+/// the executor, it follows the wall clock rather than the executor's `Clock`, so it fires at
+/// the right moment even if the system clock is changed while it is pending.
+#[allow(unused)]
+pub fn sleep_until_system_time(time: SystemTime) -> Alarm {
+    Alarm { time, timer: None }
+}
+
+/// `CLOCK_REALTIME` timerfd armed for an absolute time with `TFD_TIMER_CANCEL_ON_SET`, so that
+/// reading it fails with `ECANCELED` whenever the system clock is set.
+struct AlarmTimer {
+    fd: RawFd,
+    activity_wake_handle: ActivityWakeHandle,
+}
+
+impl AlarmTimer {
+    fn new() -> BoxResult<AlarmTimer> {
+        let fd = Errno::result(unsafe {
+            libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC)
+        })?;
+        match Executor::current().wake_on_activity(fd, Operation::READ) {
+            Ok(activity_wake_handle) => Ok(AlarmTimer {
+                fd,
+                activity_wake_handle,
+            }),
+            Err(err) => {
+                close(fd)?;
+                Err(err)
+            }
+        }
+    }
+
+    fn arm(&self, time: SystemTime) -> BoxResult<()> {
+        let time = time.duration_since(UNIX_EPOCH)?;
+        let new_value = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value: libc::timespec {
+                tv_sec: time.as_secs() as libc::time_t,
+                tv_nsec: time.subsec_nanos() as libc::c_long,
+            },
+        };
+        Errno::result(unsafe {
+            libc::timerfd_settime(
+                self.fd,
+                libc::TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET,
+                &new_value,
+                ptr::null_mut(),
+            )
+        })?;
+        Ok(())
+    }
+}
+
+impl Drop for AlarmTimer {
+    fn drop(&mut self) {
+        close(self.fd).unwrap();
+    }
+}
+
+pub struct Alarm {
+    time: SystemTime,
+    timer: Option<AlarmTimer>,
+}
+
+#[allow(unused)]
+impl Alarm {
+    pub fn deadline(&self) -> SystemTime {
+        self.time
+    }
+}
+
+impl Future for Alarm {
+    type Output = BoxResult<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if SystemTime::now() >= self.time {
+            self.timer = None;
+            return Poll::Ready(Ok(()));
+        }
+        if self.timer.is_none() {
+            let timer = match AlarmTimer::new() {
+                Ok(timer) => timer,
+                Err(err) => return Poll::Ready(Err(err)),
+            };
+            if let Err(err) = timer.arm(self.time) {
+                return Poll::Ready(Err(err));
+            }
+            self.timer = Some(timer);
+        }
+        let time = self.time;
+        let timer = self.timer.as_ref().unwrap();
+        loop {
+            let mut buf = [0; 8];
+            match read(timer.fd, &mut buf) {
+                Ok(_) => break,
+                Err(nix::Error::Sys(Errno::ECANCELED)) => {
+                    // The system clock has been set, so the deadline may have been passed or
+                    // moved further away. Check it against the new time and re-arm the timer.
+                    if SystemTime::now() >= time {
+                        break;
+                    }
+                    if let Err(err) = timer.arm(time) {
+                        return Poll::Ready(Err(err));
+                    }
+                }
+                Err(nix::Error::Sys(Errno::EAGAIN)) => {
+                    timer.activity_wake_handle.set_waker(cx.waker());
+                    return Poll::Pending;
+                }
+                Err(err) => return Poll::Ready(Err(Box::new(err))),
+            }
+        }
+        self.timer = None;
+        Poll::Ready(Ok(()))
+    }
+}
@@ -8,7 +8,6 @@ use nix::unistd::write;
 use std::collections::VecDeque;
 use std::future::ready;
 use std::future::Future;
-use std::io::Write;
 use std::pin::Pin;
 use std::task::Context;
 use std::task::Poll;
@@ -30,6 +29,16 @@ impl AsyncFile {
     pub fn fd(&self) -> RawFd {
         self.fd
     }
+
+    /// Moves data that was read ahead, or put back with `take_buffer_back`, into `buf`,
+    /// returning its length.
+    pub fn take_read_buffer(&mut self, buf: &mut [u8]) -> usize {
+        let output = usize::min(self.read_buffer.len(), buf.len());
+        for (c, byte) in buf.iter_mut().zip(self.read_buffer.drain(0..output)) {
+            *c = byte;
+        }
+        output
+    }
 }
 
 impl Drop for AsyncFile {
@@ -71,11 +80,16 @@ impl<'a> Future for ReadFuture<'a> {
     type Output = BoxResult<usize>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut eof = false;
         loop {
             let mut buf = vec![0; BUF_SIZE];
             let result = read(self.async_file.fd, &mut buf[..]);
             match result {
-                Ok(0) => return Poll::Ready(Ok(0)),
+                Ok(0) => {
+                    // Data read before the end of the file is still returned first.
+                    eof = true;
+                    break;
+                }
                 Ok(read) => self.async_file.read_buffer.extend(&buf[0..read]),
                 Err(nix::Error::Sys(Errno::EAGAIN)) => {
                     self.activity_wake_handle.set_waker(cx.waker());
@@ -85,18 +99,11 @@ impl<'a> Future for ReadFuture<'a> {
             }
         }
         match self.async_file.read_buffer.len() {
+            0 if eof => Poll::Ready(Ok(0)),
             0 => Poll::Pending,
             _ => {
-                let output = usize::min(self.async_file.read_buffer.len(), self.buf.len());
-                let drain = self
-                    .async_file
-                    .read_buffer
-                    .drain(0..output)
-                    .collect::<Vec<_>>();
-                if let Err(err) = self.buf.write_all(&drain) {
-                    return Poll::Ready(Err(Box::new(err)));
-                }
-                Poll::Ready(Ok(output))
+                let this = &mut *self;
+                Poll::Ready(Ok(this.async_file.take_read_buffer(this.buf)))
             }
         }
     }
@@ -0,0 +1,171 @@
+use crate::async_file::AsyncFile;
+use crate::executor::ActivityWakeHandle;
+use crate::executor::Executor;
+use crate::task_group::TaskGroup;
+use crate::types::*;
+use nix::errno::Errno;
+use nix::fcntl::fcntl;
+use nix::fcntl::FcntlArg;
+use nix::fcntl::OFlag;
+use nix::unistd::close;
+use std::future::Future;
+use std::io;
+use std::os::unix::io::IntoRawFd;
+use std::pin::Pin;
+use std::process;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Output;
+use std::process::Stdio;
+use std::task::Context;
+use std::task::Poll;
+
+/// Not exported by `libc` yet, see `pidfd_open(2)`.
+const SYS_PIDFD_OPEN: libc::c_long = 434;
+
+fn pidfd_open(pid: u32) -> BoxResult<RawFd> {
+    let fd = Errno::result(unsafe { libc::syscall(SYS_PIDFD_OPEN, pid as libc::pid_t, 0) })?;
+    // The pidfd is close-on-exec by default.
+    Ok(fd as RawFd)
+}
+
+fn into_async_file(fd: impl IntoRawFd) -> BoxResult<AsyncFile> {
+    let async_file = AsyncFile::from_fd(fd.into_raw_fd());
+    let flags = OFlag::from_bits_truncate(fcntl(async_file.fd(), FcntlArg::F_GETFL)?);
+    fcntl(
+        async_file.fd(),
+        FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK),
+    )?;
+    Ok(async_file)
+}
+
+/// Spawns `command` as a child process. Its piped standard streams are available as
+/// non-blocking `AsyncFile`s, and its exit is detected through a pidfd, which needs Linux 5.3.
+pub fn spawn(command: &mut Command) -> BoxResult<Child> {
+    let mut child = command.spawn()?;
+    let pidfd = match pidfd_open(child.id()) {
+        Ok(pidfd) => pidfd,
+        Err(err) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(err);
+        }
+    };
+    let activity_wake_handle = match Executor::current().wake_on_activity(pidfd, Operation::READ) {
+        Ok(activity_wake_handle) => activity_wake_handle,
+        Err(err) => {
+            close(pidfd)?;
+            return Err(err);
+        }
+    };
+    let mut child = Child {
+        stdin: None,
+        stdout: None,
+        stderr: None,
+        child,
+        pidfd,
+        activity_wake_handle,
+    };
+    child.stdin = child.child.stdin.take().map(into_async_file).transpose()?;
+    child.stdout = child.child.stdout.take().map(into_async_file).transpose()?;
+    child.stderr = child.child.stderr.take().map(into_async_file).transpose()?;
+    Ok(child)
+}
+
+/// Runs `command` to completion with its standard output and error collected, like
+/// `Command::output`, but without blocking the executor.
+#[allow(unused)]
+pub async fn output(command: &mut Command) -> BoxResult<Output> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = spawn(command)?;
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    {
+        let task_group = TaskGroup::new();
+        if let Some(child_stdout) = &mut child.stdout {
+            task_group.spawn(read_to_end(child_stdout, &mut stdout));
+        }
+        if let Some(child_stderr) = &mut child.stderr {
+            task_group.spawn(read_to_end(child_stderr, &mut stderr));
+        }
+        task_group.join().await?;
+    }
+    let status = child.wait().await?;
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+async fn read_to_end(async_file: &mut AsyncFile, output: &mut Vec<u8>) -> BoxResult<()> {
+    let mut buf = vec![0; BUF_SIZE];
+    loop {
+        match async_file.read(&mut buf[..]).await? {
+            0 => return Ok(()),
+            read => output.extend(&buf[0..read]),
+        }
+    }
+}
+
+/// Child process started by `spawn`. Like with `std::process::Child`, dropping it neither
+/// kills the process nor waits for it.
+pub struct Child {
+    pub stdin: Option<AsyncFile>,
+    pub stdout: Option<AsyncFile>,
+    pub stderr: Option<AsyncFile>,
+    child: process::Child,
+    pidfd: RawFd,
+    activity_wake_handle: ActivityWakeHandle,
+}
+
+#[allow(unused)]
+impl Child {
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Sends `SIGKILL` to the process, unless it has already been waited for.
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.child.kill()
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.child.try_wait()
+    }
+
+    /// Waits for the process to exit. The standard input is closed first, so that a process
+    /// reading it until the end does not wait forever.
+    pub fn wait(&mut self) -> Wait<'_> {
+        self.stdin = None;
+        Wait { child: self }
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        close(self.pidfd).unwrap();
+    }
+}
+
+pub struct Wait<'a> {
+    child: &'a mut Child,
+}
+
+impl<'a> Future for Wait<'a> {
+    type Output = BoxResult<ExitStatus>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.child.child.try_wait() {
+            Ok(Some(status)) => Poll::Ready(Ok(status)),
+            Ok(None) => {
+                self.child.activity_wake_handle.set_waker(cx.waker());
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(Box::new(err))),
+        }
+    }
+}
@@ -7,25 +7,48 @@ use std::task::Poll;
 use std::time::Duration;
 use std::time::Instant;
 
-pub fn async_sleep(timeout: Duration) -> impl Future<Output = ()> {
-    let time = Instant::now() + timeout;
+pub fn async_sleep(timeout: Duration) -> Sleep {
+    sleep_until(Executor::current().now() + timeout)
+}
+
+/// Creates a `Sleep` that completes at `time`.
+pub fn sleep_until(time: Instant) -> Sleep {
     let timeout_wake_handle = Executor::current().wake_at_time(time);
-    AsyncSleepFuture {
+    Sleep {
         time,
         timeout_wake_handle,
     }
 }
 
-struct AsyncSleepFuture {
+pub struct Sleep {
     time: Instant,
     timeout_wake_handle: TimeoutWakeHandle,
 }
 
-impl Future for AsyncSleepFuture {
+#[allow(unused)]
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.time
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        Executor::current().now() >= self.time
+    }
+
+    /// Changes the deadline, reusing the timer registration, so that a timeout that restarts
+    /// often, such as an idle timeout of a connection, does not register a new timer each time.
+    /// This also works after the sleep has completed, making it pending again.
+    pub fn reset(&mut self, time: Instant) {
+        self.time = time;
+        self.timeout_wake_handle.reset(time);
+    }
+}
+
+impl Future for Sleep {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if Instant::now() >= self.time {
+        if Executor::current().now() >= self.time {
             Poll::Ready(())
         } else {
             self.timeout_wake_handle.set_waker(cx.waker());
@@ -33,3 +56,94 @@ impl Future for AsyncSleepFuture {
         }
     }
 }
+
+/// What `Interval` does when ticks were missed because `tick` was not called in time.
+#[allow(unused)]
+#[derive(Clone, Copy)]
+pub enum MissedTickBehavior {
+    /// Fires the missed ticks immediately one after another, then continues on schedule.
+    Burst,
+    /// Fires once immediately and schedules the following ticks a period after that.
+    Delay,
+    /// Fires once immediately and continues with the next tick that is still in the future.
+    Skip,
+}
+
+#[allow(unused)]
+pub fn interval(period: Duration) -> Interval {
+    interval_at(Executor::current().now(), period)
+}
+
+/// Creates an `Interval` whose first tick fires at `start` and the following ones every
+/// `period` after it, regardless of how long it takes to handle each tick.
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    assert!(period > Duration::zero());
+    Interval {
+        sleep: sleep_until(start),
+        period,
+        missed_tick_behavior: MissedTickBehavior::Burst,
+    }
+}
+
+pub struct Interval {
+    sleep: Sleep,
+    period: Duration,
+    missed_tick_behavior: MissedTickBehavior,
+}
+
+#[allow(unused)]
+impl Interval {
+    /// Waits for the next tick and returns the time it was scheduled for.
+    pub fn tick(&mut self) -> Tick<'_> {
+        Tick { interval: self }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn set_missed_tick_behavior(&mut self, missed_tick_behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = missed_tick_behavior;
+    }
+
+    /// Schedules the next tick a period from now.
+    pub fn reset(&mut self) {
+        self.reset_at(Executor::current().now() + self.period);
+    }
+
+    pub fn reset_at(&mut self, time: Instant) {
+        self.sleep.reset(time);
+    }
+
+    fn next_time(&self, now: Instant) -> Instant {
+        let time = self.sleep.deadline();
+        match self.missed_tick_behavior {
+            MissedTickBehavior::Burst => time + self.period,
+            MissedTickBehavior::Delay => now + self.period,
+            MissedTickBehavior::Skip => {
+                let missed = (now - time).as_nanos() / self.period.as_nanos();
+                time + Duration::from_nanos(((missed + 1) * self.period.as_nanos()) as u64)
+            }
+        }
+    }
+}
+
+pub struct Tick<'a> {
+    interval: &'a mut Interval,
+}
+
+impl<'a> Future for Tick<'a> {
+    type Output = Instant;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.interval.sleep).poll(cx) {
+            Poll::Ready(()) => {
+                let time = self.interval.sleep.deadline();
+                let next_time = self.interval.next_time(Executor::current().now());
+                self.interval.reset_at(next_time);
+                Poll::Ready(time)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
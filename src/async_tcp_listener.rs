@@ -1,63 +1,232 @@
-use crate::executor::ActivityWakeHandle;
-use crate::executor::Executor;
+use crate::accept;
+use crate::accept::Incoming;
+use crate::accept::Listener;
+use crate::async_tcp_stream::AsyncTcpStream;
+use crate::async_tcp_stream::TcpKeepalive;
 use crate::listen_socket::ListenSocket;
+use crate::listen_socket::DEFAULT_BACKLOG;
 use crate::types::*;
-use nix::errno::Errno;
+use nix::sys::socket::getsockname;
+use nix::sys::socket::setsockopt;
+use nix::sys::socket::sockopt;
 use std::collections::VecDeque;
-use std::future::ready;
 use std::future::Future;
 use std::net::SocketAddr;
-use std::pin::Pin;
-use std::task::Context;
-use std::task::Poll;
+use std::time::Duration;
 
-pub struct AsyncTcpListener {
-    listen_socket: ListenSocket,
-    queue: VecDeque<RawFd>,
+/// Options applied to each accepted socket.
+#[derive(Clone, Default)]
+struct AcceptedSocketOptions {
+    nodelay: Option<bool>,
+    keepalive: Option<TcpKeepalive>,
+    recv_buffer_size: Option<usize>,
+    send_buffer_size: Option<usize>,
 }
 
-impl AsyncTcpListener {
-    pub fn bind<A: Into<SocketAddr>>(addr: A) -> BoxResult<AsyncTcpListener> {
-        Ok(AsyncTcpListener {
-            listen_socket: ListenSocket::bind(addr)?,
-            queue: VecDeque::new(),
-        })
+impl AcceptedSocketOptions {
+    fn validate(&self) -> BoxResult<()> {
+        if let Some(keepalive) = &self.keepalive {
+            keepalive.validate()?;
+        }
+        Ok(())
     }
 
-    pub fn incoming(&mut self) -> Box<dyn Future<Output = BoxResult<RawFd>> + Unpin + '_> {
-        let fd = self.listen_socket.fd();
-        match Executor::current().wake_on_activity(fd, Operation::READ) {
-            Ok(activity_wake_handle) => Box::new(SocketListenFuture {
-                async_tcp_listener: self,
-                activity_wake_handle,
-            }),
-            Err(err) => Box::new(ready(Err(err))),
+    fn apply(&self, async_tcp_stream: &AsyncTcpStream) -> BoxResult<()> {
+        if let Some(nodelay) = self.nodelay {
+            async_tcp_stream.set_nodelay(nodelay)?;
         }
+        if self.keepalive.is_some() {
+            async_tcp_stream.set_keepalive(self.keepalive)?;
+        }
+        if let Some(recv_buffer_size) = self.recv_buffer_size {
+            async_tcp_stream.set_recv_buffer_size(recv_buffer_size)?;
+        }
+        if let Some(send_buffer_size) = self.send_buffer_size {
+            async_tcp_stream.set_send_buffer_size(send_buffer_size)?;
+        }
+        Ok(())
     }
 }
 
-struct SocketListenFuture<'a> {
-    async_tcp_listener: &'a mut AsyncTcpListener,
-    activity_wake_handle: ActivityWakeHandle,
+/// Configures the options of the listening socket, and the ones applied to every accepted
+/// socket. Options that are not set keep the defaults of the kernel.
+pub struct TcpListenerBuilder {
+    backlog: usize,
+    reuse_port: bool,
+    ipv6_only: bool,
+    defer_accept: Option<Duration>,
+    fast_open: Option<usize>,
+    accepted_socket_options: AcceptedSocketOptions,
 }
 
-impl<'a> Future for SocketListenFuture<'a> {
-    type Output = BoxResult<RawFd>;
-
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        loop {
-            match self.async_tcp_listener.listen_socket.accept() {
-                Ok(socket) => self.async_tcp_listener.queue.push_back(socket),
-                Err(nix::Error::Sys(Errno::EAGAIN)) => {
-                    self.activity_wake_handle.set_waker(cx.waker());
-                    break;
-                }
-                Err(err) => return Poll::Ready(Err(Box::new(err))),
+#[allow(unused)]
+impl TcpListenerBuilder {
+    /// Limits the number of connections waiting to be accepted.
+    pub fn backlog(mut self, backlog: usize) -> TcpListenerBuilder {
+        self.backlog = backlog;
+        self
+    }
+
+    /// Sets `SO_REUSEPORT`, which lets several sockets, in this process or others, bind the
+    /// same address, with the kernel spreading the connections between them.
+    pub fn reuse_port(mut self, reuse_port: bool) -> TcpListenerBuilder {
+        self.reuse_port = reuse_port;
+        self
+    }
+
+    /// Sets `IPV6_V6ONLY`, so that an IPv6 socket does not accept IPv4 connections. It is
+    /// ignored for IPv4 addresses.
+    pub fn ipv6_only(mut self, ipv6_only: bool) -> TcpListenerBuilder {
+        self.ipv6_only = ipv6_only;
+        self
+    }
+
+    /// Sets `TCP_DEFER_ACCEPT`, so that connections are only accepted once the client has
+    /// sent data, or the timeout, in whole seconds, has passed.
+    pub fn defer_accept(mut self, timeout: Duration) -> TcpListenerBuilder {
+        self.defer_accept = Some(timeout);
+        self
+    }
+
+    /// Sets `TCP_FASTOPEN`, which lets clients send data along with the SYN, with at most
+    /// `queue_length` such connections pending.
+    pub fn fast_open(mut self, queue_length: usize) -> TcpListenerBuilder {
+        self.fast_open = Some(queue_length);
+        self
+    }
+
+    /// See `AsyncTcpStream::set_nodelay`.
+    pub fn nodelay(mut self, nodelay: bool) -> TcpListenerBuilder {
+        self.accepted_socket_options.nodelay = Some(nodelay);
+        self
+    }
+
+    /// See `AsyncTcpStream::set_keepalive`. The settings are checked by `bind` and `adopt`.
+    pub fn keepalive(mut self, keepalive: TcpKeepalive) -> TcpListenerBuilder {
+        self.accepted_socket_options.keepalive = Some(keepalive);
+        self
+    }
+
+    /// See `AsyncTcpStream::set_recv_buffer_size`.
+    pub fn recv_buffer_size(mut self, size: usize) -> TcpListenerBuilder {
+        self.accepted_socket_options.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// See `AsyncTcpStream::set_send_buffer_size`.
+    pub fn send_buffer_size(mut self, size: usize) -> TcpListenerBuilder {
+        self.accepted_socket_options.send_buffer_size = Some(size);
+        self
+    }
+
+    pub fn bind<A: Into<SocketAddr>>(self, addr: A) -> BoxResult<AsyncTcpListener> {
+        let addr = addr.into();
+        // Check the settings up front, so that a bad one fails here and not on every accept.
+        self.accepted_socket_options.validate()?;
+        let defer_accept = match self.defer_accept {
+            Some(timeout) => Some(whole_seconds(
+                "defer accept timeout",
+                timeout,
+                0,
+                libc::c_int::MAX as u64,
+            )?),
+            None => None,
+        };
+        let listen_socket = ListenSocket::bind_with(addr, self.backlog, |fd| {
+            if self.reuse_port {
+                setsockopt(fd, sockopt::ReusePort, &true)?;
             }
+            if self.ipv6_only && addr.is_ipv6() {
+                set_int_sockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, 1)?;
+            }
+            if let Some(timeout) = defer_accept {
+                set_int_sockopt(fd, libc::IPPROTO_TCP, libc::TCP_DEFER_ACCEPT, timeout)?;
+            }
+            if let Some(queue_length) = self.fast_open {
+                set_int_sockopt(
+                    fd,
+                    libc::IPPROTO_TCP,
+                    libc::TCP_FASTOPEN,
+                    queue_length as libc::c_int,
+                )?;
+            }
+            Ok(())
+        })?;
+        self.adopt(listen_socket)
+    }
+
+    /// Like `bind`, but takes a socket that is already listening, such as one passed by another
+    /// process, so only the options for accepted sockets apply.
+    pub fn adopt(self, listen_socket: ListenSocket) -> BoxResult<AsyncTcpListener> {
+        self.accepted_socket_options.validate()?;
+        Ok(AsyncTcpListener {
+            listen_socket,
+            accepted_socket_options: self.accepted_socket_options,
+            queue: VecDeque::new(),
+        })
+    }
+}
+
+pub struct AsyncTcpListener {
+    listen_socket: ListenSocket,
+    accepted_socket_options: AcceptedSocketOptions,
+    queue: VecDeque<(AsyncTcpStream, SocketAddr)>,
+}
+
+impl AsyncTcpListener {
+    pub fn builder() -> TcpListenerBuilder {
+        TcpListenerBuilder {
+            backlog: DEFAULT_BACKLOG,
+            reuse_port: false,
+            ipv6_only: false,
+            defer_accept: None,
+            fast_open: None,
+            accepted_socket_options: AcceptedSocketOptions::default(),
         }
-        match self.async_tcp_listener.queue.pop_front() {
-            None => Poll::Pending,
-            Some(fd) => Poll::Ready(Ok(fd)),
-        }
+    }
+
+    pub fn bind<A: Into<SocketAddr>>(addr: A) -> BoxResult<AsyncTcpListener> {
+        AsyncTcpListener::builder().bind(addr)
+    }
+
+    /// Returns the listening socket, which stays owned by the listener.
+    #[allow(unused)]
+    pub fn fd(&self) -> RawFd {
+        self.listen_socket.fd()
+    }
+
+    #[allow(unused)]
+    pub fn local_addr(&self) -> BoxResult<SocketAddr> {
+        to_socket_addr(getsockname(self.listen_socket.fd())?)
+    }
+
+    /// Waits for a connection and returns its stream along with the address of the peer.
+    pub fn accept(
+        &mut self,
+    ) -> Box<dyn Future<Output = BoxResult<(AsyncTcpStream, SocketAddr)>> + Unpin + '_> {
+        let accepted_socket_options = &self.accepted_socket_options;
+        accept::accept(
+            &mut self.listen_socket,
+            &mut self.queue,
+            move |socket, sock_addr| {
+                let async_tcp_stream = AsyncTcpStream::from_fd(socket);
+                accepted_socket_options.apply(&async_tcp_stream)?;
+                Ok((async_tcp_stream, to_socket_addr(sock_addr)?))
+            },
+        )
+    }
+
+    /// Returns a never-ending stream of the accepted connections.
+    #[allow(unused)]
+    pub fn incoming(&mut self) -> Incoming<'_, AsyncTcpListener> {
+        Incoming::new(self)
+    }
+}
+
+impl Listener for AsyncTcpListener {
+    type Connection = (AsyncTcpStream, SocketAddr);
+
+    fn accept(&mut self) -> Box<dyn Future<Output = BoxResult<Self::Connection>> + Unpin + '_> {
+        AsyncTcpListener::accept(self)
     }
 }
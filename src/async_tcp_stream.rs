@@ -0,0 +1,208 @@
+use crate::async_file::AsyncFile;
+use crate::executor::ActivityWakeHandle;
+use crate::executor::Executor;
+use crate::types::*;
+use nix::errno::Errno;
+use nix::sys::socket::connect;
+use nix::sys::socket::getpeername;
+use nix::sys::socket::getsockname;
+use nix::sys::socket::getsockopt;
+use nix::sys::socket::setsockopt;
+use nix::sys::socket::socket;
+use nix::sys::socket::sockopt;
+use nix::sys::socket::InetAddr;
+use nix::sys::socket::SockAddr;
+use nix::sys::socket::SockFlag;
+use nix::sys::socket::SockType;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+/// Settings of TCP keepalive probes, which detect dead peers on idle connections.
+#[derive(Clone, Copy)]
+pub struct TcpKeepalive {
+    /// Time the connection has to be idle before the first probe is sent.
+    pub idle: Duration,
+    /// Time between unanswered probes.
+    pub interval: Duration,
+    /// Number of unanswered probes after which the connection is dropped.
+    pub count: u32,
+}
+
+// Limits of the Linux TCP stack for the keepalive options.
+const MAX_KEEPALIVE_SECS: u64 = 32767;
+const MAX_KEEPALIVE_COUNT: u32 = 127;
+
+impl TcpKeepalive {
+    /// Checks that the settings can be passed to the kernel as they are.
+    pub fn validate(&self) -> BoxResult<()> {
+        whole_seconds("keepalive idle time", self.idle, 1, MAX_KEEPALIVE_SECS)?;
+        whole_seconds("keepalive interval", self.interval, 1, MAX_KEEPALIVE_SECS)?;
+        if self.count < 1 || self.count > MAX_KEEPALIVE_COUNT {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "keepalive count must be between 1 and {}, got {}",
+                    MAX_KEEPALIVE_COUNT, self.count
+                ),
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// TCP connection that closes its socket on drop.
+pub struct AsyncTcpStream {
+    async_file: AsyncFile,
+}
+
+#[allow(unused)]
+impl AsyncTcpStream {
+    /// Takes ownership of a connected non-blocking socket.
+    pub fn from_fd(fd: RawFd) -> AsyncTcpStream {
+        AsyncTcpStream {
+            async_file: AsyncFile::from_fd(fd),
+        }
+    }
+
+    /// Opens a connection to `addr`, waiting for the handshake without blocking the executor.
+    pub async fn connect<A: Into<SocketAddr>>(addr: A) -> BoxResult<AsyncTcpStream> {
+        let addr = addr.into();
+        let async_tcp_stream = AsyncTcpStream::from_fd(socket(
+            address_family(&addr),
+            SockType::Stream,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            None,
+        )?);
+        match connect(
+            async_tcp_stream.fd(),
+            &SockAddr::Inet(InetAddr::from_std(&addr)),
+        ) {
+            Ok(()) => return Ok(async_tcp_stream),
+            Err(nix::Error::Sys(Errno::EINPROGRESS)) => {}
+            Err(err) => return Err(Box::new(err)),
+        }
+        ConnectFuture {
+            fd: async_tcp_stream.fd(),
+            activity_wake_handle: Executor::current()
+                .wake_on_activity(async_tcp_stream.fd(), Operation::WRITE)?,
+        }
+        .await?;
+        Ok(async_tcp_stream)
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.async_file.fd()
+    }
+
+    pub fn peer_addr(&self) -> BoxResult<SocketAddr> {
+        to_socket_addr(getpeername(self.fd())?)
+    }
+
+    pub fn local_addr(&self) -> BoxResult<SocketAddr> {
+        to_socket_addr(getsockname(self.fd())?)
+    }
+
+    /// Sets `TCP_NODELAY`, which disables Nagle's algorithm, so that small writes are sent
+    /// immediately instead of being coalesced.
+    pub fn set_nodelay(&self, nodelay: bool) -> BoxResult<()> {
+        setsockopt(self.fd(), sockopt::TcpNoDelay, &nodelay)?;
+        Ok(())
+    }
+
+    /// Enables keepalive probes with the given settings, which are in whole seconds, or
+    /// disables them with `None`. Fails without changing the socket if the settings are out of
+    /// range.
+    pub fn set_keepalive(&self, keepalive: Option<TcpKeepalive>) -> BoxResult<()> {
+        if let Some(keepalive) = &keepalive {
+            keepalive.validate()?;
+        }
+        setsockopt(self.fd(), sockopt::KeepAlive, &keepalive.is_some())?;
+        if let Some(keepalive) = keepalive {
+            setsockopt(
+                self.fd(),
+                sockopt::TcpKeepIdle,
+                &(keepalive.idle.as_secs() as u32),
+            )?;
+            set_int_sockopt(
+                self.fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_KEEPINTVL,
+                keepalive.interval.as_secs() as libc::c_int,
+            )?;
+            set_int_sockopt(
+                self.fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_KEEPCNT,
+                keepalive.count as libc::c_int,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Sets `SO_RCVBUF`. The kernel doubles the size for its bookkeeping.
+    pub fn set_recv_buffer_size(&self, size: usize) -> BoxResult<()> {
+        setsockopt(self.fd(), sockopt::RcvBuf, &size)?;
+        Ok(())
+    }
+
+    /// Sets `SO_SNDBUF`. The kernel doubles the size for its bookkeeping.
+    pub fn set_send_buffer_size(&self, size: usize) -> BoxResult<()> {
+        setsockopt(self.fd(), sockopt::SndBuf, &size)?;
+        Ok(())
+    }
+}
+
+/// Waits for a non-blocking `connect` to finish. The socket becomes writable either way, so
+/// the outcome is taken from `SO_ERROR`, and whether it is still in progress from
+/// `getpeername`.
+struct ConnectFuture {
+    fd: RawFd,
+    activity_wake_handle: ActivityWakeHandle,
+}
+
+impl Future for ConnectFuture {
+    type Output = BoxResult<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match getsockopt(self.fd, sockopt::SocketError) {
+            Ok(0) => {}
+            Ok(err) => return Poll::Ready(Err(Box::new(io::Error::from_raw_os_error(err)))),
+            Err(err) => return Poll::Ready(Err(Box::new(err))),
+        }
+        match getpeername(self.fd) {
+            Ok(_) => Poll::Ready(Ok(())),
+            Err(nix::Error::Sys(Errno::ENOTCONN)) => {
+                self.activity_wake_handle.set_waker(cx.waker());
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(Box::new(err))),
+        }
+    }
+}
+
+impl AsyncRead for AsyncTcpStream {
+    fn read<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+    ) -> Box<dyn Future<Output = BoxResult<usize>> + Unpin + 'a> {
+        self.async_file.read(buf)
+    }
+
+    fn take_buffer_back(&mut self, buf: &[u8]) {
+        self.async_file.take_buffer_back(buf);
+    }
+}
+
+impl AsyncWrite for AsyncTcpStream {
+    fn write<'a>(
+        &'a mut self,
+        buf: &'a [u8],
+    ) -> Box<dyn Future<Output = BoxResult<usize>> + Unpin + 'a> {
+        self.async_file.write(buf)
+    }
+}
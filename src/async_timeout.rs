@@ -0,0 +1,75 @@
+use crate::executor::Executor;
+use crate::executor::TimeoutWakeHandle;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Debug)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl Error for Elapsed {}
+
+#[allow(unused)]
+pub fn timeout<F>(timeout: Duration, future: F) -> Timeout<F>
+where
+    F: Future,
+{
+    timeout_at(Executor::current().now() + timeout, future)
+}
+
+/// Runs `future` until `time`. If it has not completed by then, it is dropped and the result
+/// is `Err(Elapsed)`.
+pub fn timeout_at<F>(time: Instant, future: F) -> Timeout<F>
+where
+    F: Future,
+{
+    let timeout_wake_handle = Executor::current().wake_at_time(time);
+    Timeout {
+        future: Some(Box::pin(future)),
+        time,
+        timeout_wake_handle,
+    }
+}
+
+pub struct Timeout<F>
+where
+    F: Future,
+{
+    future: Option<Pin<Box<F>>>,
+    time: Instant,
+    timeout_wake_handle: TimeoutWakeHandle,
+}
+
+impl<F> Future for Timeout<F>
+where
+    F: Future,
+{
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(future) = &mut self.future {
+            if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                self.future = None;
+                return Poll::Ready(Ok(output));
+            }
+        }
+        if Executor::current().now() >= self.time {
+            self.future = None;
+            Poll::Ready(Err(Elapsed))
+        } else {
+            self.timeout_wake_handle.set_waker(cx.waker());
+            Poll::Pending
+        }
+    }
+}
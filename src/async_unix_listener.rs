@@ -0,0 +1,57 @@
+use crate::accept;
+use crate::accept::Incoming;
+use crate::accept::Listener;
+use crate::async_unix_stream::AsyncUnixStream;
+use crate::types::*;
+use crate::unix_listen_socket::UnixListenSocket;
+use nix::sys::socket::getsockname;
+use nix::sys::socket::UnixAddr;
+use std::collections::VecDeque;
+use std::future::Future;
+
+pub struct AsyncUnixListener {
+    listen_socket: UnixListenSocket,
+    queue: VecDeque<(AsyncUnixStream, UnixAddr)>,
+}
+
+#[allow(unused)]
+impl AsyncUnixListener {
+    /// See `UnixListenSocket::bind`.
+    pub fn bind(addr: &UnixAddr) -> BoxResult<AsyncUnixListener> {
+        Ok(AsyncUnixListener {
+            listen_socket: UnixListenSocket::bind(addr)?,
+            queue: VecDeque::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> BoxResult<UnixAddr> {
+        to_unix_addr(getsockname(self.listen_socket.fd())?)
+    }
+
+    /// Waits for a connection and returns its stream along with the address of the peer.
+    pub fn accept(
+        &mut self,
+    ) -> Box<dyn Future<Output = BoxResult<(AsyncUnixStream, UnixAddr)>> + Unpin + '_> {
+        accept::accept(
+            &mut self.listen_socket,
+            &mut self.queue,
+            |socket, sock_addr| {
+                let async_unix_stream = AsyncUnixStream::from_fd(socket);
+                Ok((async_unix_stream, to_unix_addr(sock_addr)?))
+            },
+        )
+    }
+
+    /// Returns a never-ending stream of the accepted connections.
+    pub fn incoming(&mut self) -> Incoming<'_, AsyncUnixListener> {
+        Incoming::new(self)
+    }
+}
+
+impl Listener for AsyncUnixListener {
+    type Connection = (AsyncUnixStream, UnixAddr);
+
+    fn accept(&mut self) -> Box<dyn Future<Output = BoxResult<Self::Connection>> + Unpin + '_> {
+        AsyncUnixListener::accept(self)
+    }
+}
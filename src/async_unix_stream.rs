@@ -0,0 +1,278 @@
+use crate::async_file::AsyncFile;
+use crate::executor::ActivityWakeHandle;
+use crate::executor::Executor;
+use crate::types::*;
+use nix::cmsg_space;
+use nix::errno::Errno;
+use nix::sys::socket::connect;
+use nix::sys::socket::getpeername;
+use nix::sys::socket::getsockname;
+use nix::sys::socket::getsockopt;
+use nix::sys::socket::recvmsg;
+use nix::sys::socket::sendmsg;
+use nix::sys::socket::socket;
+use nix::sys::socket::socketpair;
+use nix::sys::socket::sockopt;
+use nix::sys::socket::AddressFamily;
+use nix::sys::socket::ControlMessage;
+use nix::sys::socket::ControlMessageOwned;
+use nix::sys::socket::MsgFlags;
+use nix::sys::socket::SockAddr;
+use nix::sys::socket::SockFlag;
+use nix::sys::socket::SockType;
+use nix::sys::socket::UnixAddr;
+use nix::sys::socket::UnixCredentials;
+use nix::sys::uio::IoVec;
+use nix::unistd::close;
+use std::future::ready;
+use std::future::Future;
+use std::io;
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+/// Most descriptors that can be passed in one message, `SCM_MAX_FD` in the kernel.
+const MAX_FDS: usize = 253;
+
+/// Unix domain stream connection that closes its socket on drop. It reads with `recvmsg` and
+/// never reads ahead, so descriptors passed along with the data are not lost, even when it is
+/// read with `read`.
+pub struct AsyncUnixStream {
+    async_file: AsyncFile,
+    /// Descriptors that were received but not returned by `recv_with_fds` yet.
+    received_fds: Vec<RawFd>,
+}
+
+#[allow(unused)]
+impl AsyncUnixStream {
+    /// Takes ownership of a connected non-blocking socket.
+    pub fn from_fd(fd: RawFd) -> AsyncUnixStream {
+        AsyncUnixStream {
+            async_file: AsyncFile::from_fd(fd),
+            received_fds: Vec::new(),
+        }
+    }
+
+    /// Connects to the listener at `addr`. Unlike TCP, this completes immediately, so there is
+    /// nothing to wait for, but it fails with `EAGAIN` when the backlog of the listener is full.
+    pub fn connect(addr: &UnixAddr) -> BoxResult<AsyncUnixStream> {
+        let async_unix_stream = AsyncUnixStream::from_fd(socket(
+            AddressFamily::Unix,
+            SockType::Stream,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            None,
+        )?);
+        connect(async_unix_stream.fd(), &SockAddr::Unix(*addr))?;
+        Ok(async_unix_stream)
+    }
+
+    /// Creates a pair of sockets connected to each other, for example to pass one to a child
+    /// process.
+    pub fn pair() -> BoxResult<(AsyncUnixStream, AsyncUnixStream)> {
+        let (fd1, fd2) = socketpair(
+            AddressFamily::Unix,
+            SockType::Stream,
+            None,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+        )?;
+        Ok((AsyncUnixStream::from_fd(fd1), AsyncUnixStream::from_fd(fd2)))
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.async_file.fd()
+    }
+
+    pub fn peer_addr(&self) -> BoxResult<UnixAddr> {
+        to_unix_addr(getpeername(self.fd())?)
+    }
+
+    pub fn local_addr(&self) -> BoxResult<UnixAddr> {
+        to_unix_addr(getsockname(self.fd())?)
+    }
+
+    /// Returns the process ID and the user and group IDs of the peer, as they were when it
+    /// connected, which can be used to authenticate it.
+    pub fn peer_cred(&self) -> BoxResult<UnixCredentials> {
+        Ok(getsockopt(self.fd(), sockopt::PeerCredentials)?)
+    }
+
+    /// Writes data from `buf` along with the descriptors `fds`, which the peer gets duplicates
+    /// of with `recv_with_fds`, so they stay open here. They are attached to the first byte
+    /// written, which is why `buf` must not be empty.
+    pub fn send_with_fds<'a>(
+        &'a mut self,
+        buf: &'a [u8],
+        fds: &'a [RawFd],
+    ) -> Box<dyn Future<Output = BoxResult<usize>> + Unpin + 'a> {
+        if buf.is_empty() {
+            return Box::new(ready(Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "descriptors have to be sent along with data",
+            )
+            .into())));
+        }
+        let fd = self.fd();
+        match Executor::current().wake_on_activity(fd, Operation::WRITE) {
+            Ok(activity_wake_handle) => Box::new(SendWithFdsFuture {
+                async_unix_stream: self,
+                buf,
+                fds,
+                activity_wake_handle,
+            }),
+            Err(err) => Box::new(ready(Err(err))),
+        }
+    }
+
+    /// Reads data into `buf`, like `read`, along with the descriptors received so far, which are
+    /// close-on-exec and owned by the caller. These include descriptors that came with data
+    /// that was consumed by `read` before.
+    pub fn recv_with_fds<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+    ) -> Box<dyn Future<Output = BoxResult<(usize, Vec<RawFd>)>> + Unpin + 'a> {
+        match self.recv(buf) {
+            Ok(recv_future) => Box::new(RecvWithFdsFuture { recv_future }),
+            Err(err) => Box::new(ready(Err(err))),
+        }
+    }
+
+    fn recv<'a>(&'a mut self, buf: &'a mut [u8]) -> BoxResult<RecvFuture<'a>> {
+        let activity_wake_handle =
+            Executor::current().wake_on_activity(self.fd(), Operation::READ)?;
+        Ok(RecvFuture {
+            async_unix_stream: self,
+            buf,
+            activity_wake_handle,
+        })
+    }
+}
+
+impl Drop for AsyncUnixStream {
+    fn drop(&mut self) {
+        for &fd in &self.received_fds {
+            close(fd).unwrap();
+        }
+    }
+}
+
+struct SendWithFdsFuture<'a> {
+    async_unix_stream: &'a mut AsyncUnixStream,
+    buf: &'a [u8],
+    fds: &'a [RawFd],
+    activity_wake_handle: ActivityWakeHandle,
+}
+
+impl<'a> Future for SendWithFdsFuture<'a> {
+    type Output = BoxResult<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = sendmsg(
+            self.async_unix_stream.fd(),
+            &[IoVec::from_slice(self.buf)],
+            &[ControlMessage::ScmRights(self.fds)],
+            MsgFlags::empty(),
+            None,
+        );
+        match result {
+            Ok(written) => Poll::Ready(Ok(written)),
+            Err(nix::Error::Sys(Errno::EAGAIN)) => {
+                self.activity_wake_handle.set_waker(cx.waker());
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(Box::new(err))),
+        }
+    }
+}
+
+/// Receives data into `buf`, keeping the descriptors that come with it in the stream.
+struct RecvFuture<'a> {
+    async_unix_stream: &'a mut AsyncUnixStream,
+    buf: &'a mut [u8],
+    activity_wake_handle: ActivityWakeHandle,
+}
+
+impl<'a> Future for RecvFuture<'a> {
+    type Output = BoxResult<usize>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        // Data put back with `take_buffer_back` comes first, to keep the stream in order.
+        let read = this.async_unix_stream.async_file.take_read_buffer(this.buf);
+        if read > 0 {
+            return Poll::Ready(Ok(read));
+        }
+        let mut cmsg_buffer = cmsg_space!([RawFd; MAX_FDS]);
+        let result = recvmsg(
+            this.async_unix_stream.fd(),
+            &[IoVec::from_mut_slice(this.buf)],
+            Some(&mut cmsg_buffer),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        );
+        match result {
+            Ok(msg) => {
+                for cmsg in msg.cmsgs() {
+                    if let ControlMessageOwned::ScmRights(received) = cmsg {
+                        this.async_unix_stream.received_fds.extend(received);
+                    }
+                }
+                if msg.flags.contains(MsgFlags::MSG_CTRUNC) {
+                    return Poll::Ready(Err(Box::new(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "control messages were truncated",
+                    ))));
+                }
+                Poll::Ready(Ok(msg.bytes))
+            }
+            Err(nix::Error::Sys(Errno::EAGAIN)) => {
+                this.activity_wake_handle.set_waker(cx.waker());
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(Box::new(err))),
+        }
+    }
+}
+
+struct RecvWithFdsFuture<'a> {
+    recv_future: RecvFuture<'a>,
+}
+
+impl<'a> Future for RecvWithFdsFuture<'a> {
+    type Output = BoxResult<(usize, Vec<RawFd>)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.recv_future).poll(cx) {
+            Poll::Ready(Ok(read)) => {
+                let fds = self.recv_future.async_unix_stream.received_fds.split_off(0);
+                Poll::Ready(Ok((read, fds)))
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncRead for AsyncUnixStream {
+    fn read<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+    ) -> Box<dyn Future<Output = BoxResult<usize>> + Unpin + 'a> {
+        match self.recv(buf) {
+            Ok(recv_future) => Box::new(recv_future),
+            Err(err) => Box::new(ready(Err(err))),
+        }
+    }
+
+    fn take_buffer_back(&mut self, buf: &[u8]) {
+        self.async_file.take_buffer_back(buf);
+    }
+}
+
+impl AsyncWrite for AsyncUnixStream {
+    fn write<'a>(
+        &'a mut self,
+        buf: &'a [u8],
+    ) -> Box<dyn Future<Output = BoxResult<usize>> + Unpin + 'a> {
+        self.async_file.write(buf)
+    }
+}
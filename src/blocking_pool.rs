@@ -0,0 +1,140 @@
+use crate::join_handle;
+use crate::join_handle::JoinError;
+use crate::join_handle::JoinHandle;
+use std::collections::VecDeque;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+type JobDoneCallback = Box<dyn Fn() + Send + Sync>;
+
+struct BlockingPoolState {
+    queue: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+    notified: usize,
+    /// Jobs that are queued or running.
+    in_flight: usize,
+}
+
+struct BlockingPoolShared {
+    state: Mutex<BlockingPoolState>,
+    condvar: Condvar,
+    max_threads: usize,
+    keep_alive: Duration,
+    job_done: JobDoneCallback,
+}
+
+/// Pool of threads for running blocking closures. Threads are started on demand, up to
+/// `max_threads`, and exit after staying idle for `keep_alive`. Jobs that arrive while all
+/// threads are busy wait in a queue. `job_done` is called on the pool thread after each job,
+/// so that the executor can notice when the pool has become idle.
+pub struct BlockingPool {
+    shared: Arc<BlockingPoolShared>,
+}
+
+impl BlockingPool {
+    pub fn new(
+        max_threads: usize,
+        keep_alive: Duration,
+        job_done: impl Fn() + Send + Sync + 'static,
+    ) -> BlockingPool {
+        BlockingPool {
+            shared: Arc::new(BlockingPoolShared {
+                state: Mutex::new(BlockingPoolState {
+                    queue: VecDeque::new(),
+                    threads: 0,
+                    idle: 0,
+                    notified: 0,
+                    in_flight: 0,
+                }),
+                condvar: Condvar::new(),
+                max_threads,
+                keep_alive,
+                job_done: Box::new(job_done),
+            }),
+        }
+    }
+
+    /// Runs `f` on the pool. The returned `JoinHandle` wakes whoever awaits it from the pool
+    /// thread, which is safe as task wakers are `Send + Sync`. Aborting the handle only has an
+    /// effect if `f` has not started running yet.
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (join_sender, join_handle) = join_handle::channel();
+        let abort_state = join_sender.abort_state();
+        self.execute(Box::new(move || {
+            if abort_state.is_aborted() {
+                return;
+            }
+            match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok(output) => join_sender.send(output),
+                Err(payload) => join_sender.fail(JoinError::Panicked(payload)),
+            }
+        }));
+        join_handle
+    }
+
+    /// Returns the number of jobs that are queued or running.
+    pub fn in_flight(&self) -> usize {
+        self.shared.state.lock().unwrap().in_flight
+    }
+
+    fn execute(&self, job: Job) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.queue.push_back(job);
+        state.in_flight += 1;
+        if state.idle > state.notified {
+            state.notified += 1;
+            self.shared.condvar.notify_one();
+        } else if state.threads < self.shared.max_threads {
+            state.threads += 1;
+            let shared = self.shared.clone();
+            thread::Builder::new()
+                .name("blocking".to_string())
+                .spawn(move || run_worker(&shared))
+                .unwrap();
+        }
+    }
+}
+
+fn run_worker(shared: &BlockingPoolShared) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        if let Some(job) = state.queue.pop_front() {
+            drop(state);
+            job();
+            state = shared.state.lock().unwrap();
+            state.in_flight -= 1;
+            (shared.job_done)();
+            continue;
+        }
+        state.idle += 1;
+        loop {
+            let (new_state, wait_result) = shared
+                .condvar
+                .wait_timeout(state, shared.keep_alive)
+                .unwrap();
+            state = new_state;
+            if state.notified > 0 {
+                state.notified -= 1;
+                break;
+            }
+            if wait_result.timed_out() {
+                state.idle -= 1;
+                state.threads -= 1;
+                return;
+            }
+        }
+        state.idle -= 1;
+    }
+}
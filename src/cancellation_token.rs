@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+
+struct CancellationState {
+    cancelled: bool,
+    last_waker_id: u64,
+    wakers: HashMap<u64, Waker>,
+    children: Vec<Weak<Mutex<CancellationState>>>,
+    /// Keeps the parent alive, so that a child is still cancelled along with its grandparent
+    /// after all the clones of its parent are dropped.
+    #[allow(unused)]
+    parent: Option<Arc<Mutex<CancellationState>>>,
+}
+
+/// Flag for asking tasks to stop, which can be awaited. Clones share the same flag, and child
+/// tokens are cancelled along with their parent, but not the other way around. Tokens are
+/// `Send + Sync`, so they also work across the threads of `MultiThreadExecutor`.
+#[derive(Clone)]
+pub struct CancellationToken {
+    state: Arc<Mutex<CancellationState>>,
+}
+
+#[allow(unused)]
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::with_parent(false, None)
+    }
+
+    fn with_parent(
+        cancelled: bool,
+        parent: Option<Arc<Mutex<CancellationState>>>,
+    ) -> CancellationToken {
+        CancellationToken {
+            state: Arc::new(Mutex::new(CancellationState {
+                cancelled,
+                last_waker_id: 0,
+                wakers: HashMap::new(),
+                children: Vec::new(),
+                parent,
+            })),
+        }
+    }
+
+    /// Creates a token that is cancelled when this one is, or when it is cancelled itself.
+    pub fn child_token(&self) -> CancellationToken {
+        let mut state = self.state.lock().unwrap();
+        if state.cancelled {
+            return CancellationToken::with_parent(true, None);
+        }
+        let child = CancellationToken::with_parent(false, Some(self.state.clone()));
+        state.children.retain(|child| child.strong_count() > 0);
+        state.children.push(Arc::downgrade(&child.state));
+        child
+    }
+
+    /// Cancels the token and all of its children, waking the tasks awaiting them.
+    pub fn cancel(&self) {
+        let (wakers, children) = {
+            let mut state = self.state.lock().unwrap();
+            if state.cancelled {
+                return;
+            }
+            state.cancelled = true;
+            let wakers = state
+                .wakers
+                .drain()
+                .map(|(_, waker)| waker)
+                .collect::<Vec<_>>();
+            (wakers, state.children.split_off(0))
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+        for child in children {
+            if let Some(state) = child.upgrade() {
+                CancellationToken { state }.cancel();
+            }
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.lock().unwrap().cancelled
+    }
+
+    /// Returns a future that completes once the token is cancelled.
+    pub fn cancelled(&self) -> Cancelled<'_> {
+        Cancelled {
+            token: self,
+            waker_id: None,
+        }
+    }
+
+    /// Runs `future` until it completes, returning its output, or until the token is
+    /// cancelled, in which case it is dropped and the result is `None`.
+    pub fn run_until_cancelled<F>(&self, future: F) -> RunUntilCancelled<'_, F>
+    where
+        F: Future,
+    {
+        RunUntilCancelled {
+            future: Box::pin(future),
+            cancelled: self.cancelled(),
+        }
+    }
+}
+
+pub struct Cancelled<'a> {
+    token: &'a CancellationToken,
+    waker_id: Option<u64>,
+}
+
+impl<'a> Future for Cancelled<'a> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.token.state.lock().unwrap();
+        if state.cancelled {
+            return Poll::Ready(());
+        }
+        let waker_id = match self.waker_id {
+            Some(waker_id) => waker_id,
+            None => {
+                state.last_waker_id += 1;
+                state.last_waker_id
+            }
+        };
+        state.wakers.insert(waker_id, cx.waker().clone());
+        drop(state);
+        self.waker_id = Some(waker_id);
+        Poll::Pending
+    }
+}
+
+impl<'a> Drop for Cancelled<'a> {
+    fn drop(&mut self) {
+        if let Some(waker_id) = self.waker_id {
+            self.token.state.lock().unwrap().wakers.remove(&waker_id);
+        }
+    }
+}
+
+pub struct RunUntilCancelled<'a, F>
+where
+    F: Future,
+{
+    future: Pin<Box<F>>,
+    cancelled: Cancelled<'a>,
+}
+
+impl<'a, F> Future for RunUntilCancelled<'a, F>
+where
+    F: Future,
+{
+    type Output = Option<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(()) = Pin::new(&mut self.cancelled).poll(cx) {
+            return Poll::Ready(None);
+        }
+        match self.future.as_mut().poll(cx) {
+            Poll::Ready(output) => Poll::Ready(Some(output)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Source of time for the executor and its timers.
+pub trait Clock {
+    fn now(&self) -> Instant;
+
+    /// Returns how long the executor may block in `epoll_wait` when it has nothing to run and
+    /// its nearest timer is due at `time`, which is later than `now`. `None` means waiting for
+    /// FD activity only.
+    fn timeout_until(&self, time: Instant) -> Option<Duration>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn timeout_until(&self, time: Instant) -> Option<Duration> {
+        Some(
+            time.checked_duration_since(Instant::now())
+                .unwrap_or_else(Duration::zero),
+        )
+    }
+}
+
+struct MockClockState {
+    now: Cell<Instant>,
+    auto_advance: Cell<bool>,
+}
+
+/// Virtual clock for deterministic tests. Time stands still until `advance` is called, or, with
+/// auto-advance enabled, jumps straight to the nearest timer whenever the executor has nothing
+/// to run. Clones share the same time.
+#[allow(unused)]
+#[derive(Clone)]
+pub struct MockClock {
+    state: Rc<MockClockState>,
+}
+
+#[allow(unused)]
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock {
+            state: Rc::new(MockClockState {
+                now: Cell::new(Instant::now()),
+                auto_advance: Cell::new(false),
+            }),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.state.now.set(self.state.now.get() + duration);
+    }
+
+    pub fn set_auto_advance(&self, auto_advance: bool) {
+        self.state.auto_advance.set(auto_advance);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.state.now.get()
+    }
+
+    fn timeout_until(&self, time: Instant) -> Option<Duration> {
+        if self.state.auto_advance.get() {
+            self.state.now.set(time);
+            Some(Duration::zero())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_sleep::async_sleep;
+    use crate::async_sleep::interval_at;
+    use crate::async_sleep::MissedTickBehavior;
+    use crate::async_timeout::timeout;
+    use crate::executor::Executor;
+
+    fn mock_executor() -> (Executor, MockClock) {
+        let clock = MockClock::new();
+        clock.set_auto_advance(true);
+        let executor = Executor::builder().clock(clock.clone()).build().unwrap();
+        (executor, clock)
+    }
+
+    #[test]
+    fn long_sleep_is_instant() {
+        let (executor, clock) = mock_executor();
+        let start = clock.now();
+        let real_start = Instant::now();
+        executor
+            .block_on(async { async_sleep(Duration::from_secs(60 * 60)).await })
+            .unwrap();
+        assert_eq!(clock.now() - start, Duration::from_secs(60 * 60));
+        assert!(real_start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn timeout_elapses_before_future() {
+        let (executor, clock) = mock_executor();
+        let start = clock.now();
+        let result = executor
+            .block_on(async {
+                timeout(Duration::from_secs(1), async_sleep(Duration::from_secs(60))).await
+            })
+            .unwrap();
+        assert!(result.is_err());
+        assert_eq!(clock.now() - start, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn ticks_are_one_period_apart() {
+        let period = Duration::from_secs(10);
+        for &missed_tick_behavior in &[
+            MissedTickBehavior::Burst,
+            MissedTickBehavior::Delay,
+            MissedTickBehavior::Skip,
+        ] {
+            let (executor, clock) = mock_executor();
+            let start = clock.now();
+            let times = executor
+                .block_on(async move {
+                    let mut interval = interval_at(start, period);
+                    interval.set_missed_tick_behavior(missed_tick_behavior);
+                    let mut times = Vec::new();
+                    for _ in 0..4 {
+                        interval.tick().await;
+                        times.push(clock.now());
+                    }
+                    times
+                })
+                .unwrap();
+            for (index, time) in times.into_iter().enumerate() {
+                assert_eq!(time - start, period * index as u32);
+            }
+        }
+    }
+
+    #[test]
+    fn missed_ticks() {
+        let period = Duration::from_secs(10);
+        for &(missed_tick_behavior, third_tick) in &[
+            (MissedTickBehavior::Burst, 20),
+            (MissedTickBehavior::Delay, 35),
+            (MissedTickBehavior::Skip, 30),
+        ] {
+            let (executor, clock) = mock_executor();
+            let start = clock.now();
+            let ticks = executor
+                .block_on(async move {
+                    let mut interval = interval_at(start, period);
+                    interval.set_missed_tick_behavior(missed_tick_behavior);
+                    let first = interval.tick().await;
+                    clock.advance(Duration::from_secs(25));
+                    let second = interval.tick().await;
+                    let third = interval.tick().await;
+                    vec![first, second, third]
+                })
+                .unwrap();
+            assert_eq!(
+                ticks
+                    .into_iter()
+                    .map(|tick| tick - start)
+                    .collect::<Vec<_>>(),
+                vec![Duration::zero(), period, Duration::from_secs(third_tick),]
+            );
+        }
+    }
+}
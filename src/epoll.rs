@@ -71,6 +71,10 @@ impl Epoll {
         Ok(id)
     }
 
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
     pub fn modify(&mut self, id: EpollEntryId, callback: impl Fn() + 'static) {
         self.handles.get_mut(&id).unwrap().callback = Box::new(callback);
     }
@@ -88,7 +92,9 @@ impl Epoll {
             self.fd,
             &mut epoll_events[..],
             match timeout {
-                Some(timeout) => timeout.as_millis() as isize,
+                // Round up, so that waiting for a timer that is less than a millisecond away
+                // does not turn into busy waiting.
+                Some(timeout) => ((timeout.as_nanos() + 999_999) / 1_000_000) as isize,
                 None => -1,
             },
         );
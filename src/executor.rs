@@ -1,15 +1,46 @@
+use crate::blocking_pool::BlockingPool;
+use crate::clock::Clock;
+use crate::clock::SystemClock;
 use crate::epoll::Epoll;
 use crate::epoll::EpollEntryId;
+use crate::join_handle;
+use crate::join_handle::AbortState;
+use crate::join_handle::JoinError;
+use crate::join_handle::JoinHandle;
+use crate::join_handle::JoinSender;
+use crate::timer_heap::TimerHeap;
+use crate::timer_heap::TimerId;
 use crate::types::*;
-use send_wrapper::SendWrapper;
+use nix::errno::Errno;
+use nix::sys::eventfd::eventfd;
+use nix::sys::eventfd::EfdFlags;
+use nix::sys::time::TimeSpec;
+use nix::sys::time::TimeValLike;
+use nix::sys::timerfd::ClockId;
+use nix::sys::timerfd::Expiration;
+use nix::sys::timerfd::TimerFd;
+use nix::sys::timerfd::TimerFlags;
+use nix::sys::timerfd::TimerSetTimeFlags;
+use nix::unistd::close;
+use nix::unistd::read;
+use nix::unistd::write;
+use std::any::Any;
 use std::cell::Cell;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::future::Future;
+use std::marker::PhantomData;
+use std::os::unix::io::AsRawFd;
+use std::panic;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
 use std::rc::Rc;
+use std::rc::Weak;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::task::Context;
 use std::task::Poll;
 use std::task::Wake;
@@ -17,15 +48,92 @@ use std::task::Waker;
 use std::time::Duration;
 use std::time::Instant;
 
+/// The single-threaded executor, which also owns the reactor of its thread. Unlike
+/// `MultiThreadExecutor`, it can run futures that are not `Send`.
+#[allow(unused)]
+pub type LocalExecutor = Executor;
+
 thread_local! {
-    static EXECUTOR: RefCell<Option<Executor>> = RefCell::new(None);
-    static WAKE_CALLED: Cell<bool> = Cell::new(false);
+    /// Executor that ran on the thread last. It is not kept alive by this, so that it can be
+    /// torn down while the thread-local is still accessible.
+    static EXECUTOR: RefCell<Weak<RefCell<ExecutorInner>>> = RefCell::new(Weak::new());
+}
+
+pub type TaskId = u64;
+
+/// Function called with the payload of every panic caught in a spawned task.
+type PanicHook = Rc<dyn Fn(&(dyn Any + Send))>;
+
+const DEFAULT_MAX_BLOCKING_THREADS: usize = 512;
+
+const DEFAULT_BLOCKING_KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+/// Mechanism the executor uses to wake up for its nearest timer.
+#[allow(unused)]
+#[derive(Clone, Copy)]
+pub enum TimerBackend {
+    /// Passes the timeout to `epoll_wait`, which rounds it up to whole milliseconds.
+    Epoll,
+    /// Arms a timerfd registered in the executor's `Epoll`, with nanosecond precision.
+    TimerFd,
+}
+
+/// Timerfd that is armed for the nearest timer before every `epoll_wait`.
+struct TimerFdTimer {
+    timer_fd: TimerFd,
+    time: Cell<Option<Instant>>,
+}
+
+impl TimerFdTimer {
+    fn new(epoll: &mut Epoll) -> BoxResult<TimerFdTimer> {
+        let timer_fd = TimerFd::new(
+            ClockId::CLOCK_MONOTONIC,
+            TimerFlags::TFD_NONBLOCK | TimerFlags::TFD_CLOEXEC,
+        )?;
+        let fd = timer_fd.as_raw_fd();
+        let timer_fd_timer = TimerFdTimer {
+            timer_fd,
+            time: Cell::new(None),
+        };
+        epoll.add(fd, Operation::READ, move || {
+            let mut buf = [0; 8];
+            let _ = read(fd, &mut buf);
+        })?;
+        Ok(timer_fd_timer)
+    }
+
+    /// Arms the timerfd to fire after `timeout`, unless it is already armed for `time`.
+    fn arm(&self, time: Instant, timeout: Duration) -> BoxResult<()> {
+        if self.time.get() != Some(time) {
+            // A zero expiration would disarm the timerfd instead of firing it.
+            let timeout = TimeSpec::nanoseconds(timeout.as_nanos().max(1) as i64);
+            self.timer_fd
+                .set(Expiration::OneShot(timeout), TimerSetTimeFlags::empty())?;
+            self.time.set(Some(time));
+        }
+        Ok(())
+    }
+
+    fn disarm(&self) -> BoxResult<()> {
+        if self.time.get().is_some() {
+            self.timer_fd.unset()?;
+            self.time.set(None);
+        }
+        Ok(())
+    }
 }
 
-pub type TimerId = u64;
+impl Drop for TimerFdTimer {
+    fn drop(&mut self) {
+        close(self.timer_fd.as_raw_fd()).unwrap();
+    }
+}
 
+/// Registration of an FD with the reactor of the current thread's executor. It is not `Send`,
+/// as it only refers to an entry of that reactor.
 pub struct ActivityWakeHandle {
-    id: TimerId,
+    id: EpollEntryId,
+    _not_send: PhantomData<*const ()>,
 }
 
 impl ActivityWakeHandle {
@@ -52,8 +160,11 @@ impl Drop for ActivityWakeHandle {
     }
 }
 
+/// Registration of a timer with the current thread's executor. Like `ActivityWakeHandle`, it
+/// is not `Send`.
 pub struct TimeoutWakeHandle {
-    id: EpollEntryId,
+    id: TimerId,
+    _not_send: PhantomData<*const ()>,
 }
 
 impl TimeoutWakeHandle {
@@ -62,10 +173,16 @@ impl TimeoutWakeHandle {
             .inner
             .borrow_mut()
             .timers
-            .get_mut(&self.id)
-            .unwrap()
-            .1
-            .replace(waker.clone());
+            .set_waker(self.id, waker);
+    }
+
+    /// Moves the timer to `time` without registering a new one, even if it has expired.
+    pub fn reset(&self, time: Instant) {
+        Executor::current()
+            .inner
+            .borrow_mut()
+            .timers
+            .reset(self.id, time);
     }
 }
 
@@ -75,38 +192,223 @@ impl Drop for TimeoutWakeHandle {
             .inner
             .borrow_mut()
             .timers
-            .remove(&self.id);
+            .remove(self.id);
+    }
+}
+
+/// Polls the inner future, turning a panic during `poll` into an `Err` with the panic payload.
+struct CatchUnwind<F> {
+    future: Pin<Box<F>>,
+}
+
+impl<F> Future for CatchUnwind<F>
+where
+    F: Future,
+{
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.future.as_mut();
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+/// Wraps a future into a task body that sends its output to `join_sender`, catching panics.
+pub fn task_future<F, T>(future: F, join_sender: JoinSender<T>) -> impl Future<Output = ()>
+where
+    F: Future<Output = T>,
+{
+    async move {
+        let result = CatchUnwind {
+            future: Box::pin(future),
+        }
+        .await;
+        match result {
+            Ok(output) => join_sender.send(output),
+            Err(payload) => {
+                let panic_hook = Executor::current().inner.borrow().panic_hook.clone();
+                if let Some(panic_hook) = panic_hook {
+                    panic_hook(payload.as_ref());
+                }
+                join_sender.fail(JoinError::Panicked(payload));
+            }
+        }
     }
 }
 
 struct Task {
     future: Pin<Box<dyn Future<Output = ()>>>,
+    waker: Arc<TaskWaker>,
+    abort_state: Arc<AbortState>,
+}
+
+/// IDs of the tasks that are ready to be polled. It is shared with the wakers, which may be
+/// woken from any thread, so waking a task while the executor is blocked in `epoll_wait`
+/// interrupts it by writing to an eventfd registered in the executor's `Epoll`.
+struct RunQueue {
+    queue: Mutex<VecDeque<TaskId>>,
+    event_fd: RawFd,
+    sleeping: AtomicBool,
+}
+
+impl RunQueue {
+    fn push(&self, id: TaskId) {
+        self.queue.lock().unwrap().push_back(id);
+        self.notify();
+    }
+
+    fn pop(&self) -> Option<TaskId> {
+        self.queue.lock().unwrap().pop_front()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.lock().unwrap().is_empty()
+    }
+
+    fn notify(&self) {
+        if self.sleeping.load(Ordering::SeqCst) {
+            self.interrupt();
+        }
+    }
+
+    /// Makes the current or next `epoll_wait` return, even if the executor is not sleeping yet.
+    fn interrupt(&self) {
+        match write(self.event_fd, &1u64.to_ne_bytes()) {
+            Ok(_) | Err(nix::Error::Sys(Errno::EAGAIN)) => {}
+            Err(err) => panic!("failed to notify executor: {}", err),
+        }
+    }
+}
+
+impl Drop for RunQueue {
+    fn drop(&mut self) {
+        close(self.event_fd).unwrap();
+    }
 }
 
 struct TaskWaker {
-    task: SendWrapper<RefCell<Option<Task>>>,
+    id: TaskId,
+    scheduled: AtomicBool,
+    run_queue: Arc<RunQueue>,
 }
 
 impl Wake for TaskWaker {
     fn wake(self: Arc<Self>) {
-        if let Some(task) = self.task.borrow_mut().take() {
-            Executor::current()
-                .inner
-                .borrow()
-                .queue
-                .borrow_mut()
-                .push_back(task);
-        } else {
-            WAKE_CALLED.with(|wake_called| wake_called.replace(true));
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.scheduled.swap(true, Ordering::SeqCst) {
+            self.run_queue.push(self.id);
         }
     }
 }
 
+struct MainWaker {
+    woken: AtomicBool,
+    run_queue: Arc<RunQueue>,
+}
+
+impl Wake for MainWaker {
+    fn wake(self: Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+        self.run_queue.notify();
+    }
+}
+
 struct ExecutorInner {
     epoll: Epoll,
-    queue: RefCell<VecDeque<Task>>,
-    last_timer_id: TimerId,
-    timers: HashMap<TimerId, (Instant, Option<Waker>)>,
+    run_queue: Arc<RunQueue>,
+    last_task_id: TaskId,
+    tasks: RefCell<HashMap<TaskId, Task>>,
+    timers: TimerHeap,
+    panic_hook: Option<PanicHook>,
+    blocking_pool: BlockingPool,
+    timer_fd_timer: Option<TimerFdTimer>,
+    clock: Rc<dyn Clock>,
+    /// Number of entries the executor keeps in `epoll` for its own use.
+    internal_epoll_entries: usize,
+}
+
+pub struct ExecutorBuilder {
+    max_blocking_threads: usize,
+    blocking_keep_alive: Duration,
+    timer_backend: TimerBackend,
+    clock: Rc<dyn Clock>,
+}
+
+impl ExecutorBuilder {
+    /// Limits the number of threads started for `Executor::spawn_blocking`. Closures spawned
+    /// while all of them are busy are queued.
+    #[allow(unused)]
+    pub fn max_blocking_threads(mut self, max_blocking_threads: usize) -> ExecutorBuilder {
+        assert!(max_blocking_threads > 0);
+        self.max_blocking_threads = max_blocking_threads;
+        self
+    }
+
+    /// Sets how long a thread of the blocking pool waits for new work before exiting.
+    #[allow(unused)]
+    pub fn blocking_keep_alive(mut self, blocking_keep_alive: Duration) -> ExecutorBuilder {
+        self.blocking_keep_alive = blocking_keep_alive;
+        self
+    }
+
+    /// Selects how the executor waits for timers. `TimerBackend::Epoll` is the default.
+    #[allow(unused)]
+    pub fn timer_backend(mut self, timer_backend: TimerBackend) -> ExecutorBuilder {
+        self.timer_backend = timer_backend;
+        self
+    }
+
+    /// Sets the clock used for timers, which is `SystemClock` by default.
+    #[allow(unused)]
+    pub fn clock(mut self, clock: impl Clock + 'static) -> ExecutorBuilder {
+        self.clock = Rc::new(clock);
+        self
+    }
+
+    pub fn build(self) -> BoxResult<Executor> {
+        let run_queue = Arc::new(RunQueue {
+            queue: Mutex::new(VecDeque::new()),
+            event_fd: eventfd(0, EfdFlags::EFD_NONBLOCK | EfdFlags::EFD_CLOEXEC)?,
+            sleeping: AtomicBool::new(false),
+        });
+        let mut epoll = Epoll::new()?;
+        let event_fd = run_queue.event_fd;
+        epoll.add(event_fd, Operation::READ, move || {
+            let mut buf = [0; 8];
+            let _ = read(event_fd, &mut buf);
+        })?;
+        let timer_fd_timer = match self.timer_backend {
+            TimerBackend::Epoll => None,
+            TimerBackend::TimerFd => Some(TimerFdTimer::new(&mut epoll)?),
+        };
+        let internal_epoll_entries = epoll.len();
+        let blocking_run_queue = run_queue.clone();
+        Ok(Executor {
+            inner: Rc::new(RefCell::new(ExecutorInner {
+                epoll,
+                run_queue,
+                last_task_id: 0,
+                tasks: RefCell::new(HashMap::new()),
+                timers: TimerHeap::new(),
+                panic_hook: None,
+                blocking_pool: BlockingPool::new(
+                    self.max_blocking_threads,
+                    self.blocking_keep_alive,
+                    move || blocking_run_queue.interrupt(),
+                ),
+                timer_fd_timer,
+                clock: self.clock,
+                internal_epoll_entries,
+            })),
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -116,18 +418,22 @@ pub struct Executor {
 
 impl Executor {
     pub fn new() -> BoxResult<Executor> {
-        Ok(Executor {
-            inner: Rc::new(RefCell::new(ExecutorInner {
-                epoll: Epoll::new()?,
-                queue: RefCell::new(VecDeque::new()),
-                last_timer_id: 0,
-                timers: HashMap::new(),
-            })),
-        })
+        Executor::builder().build()
+    }
+
+    pub fn builder() -> ExecutorBuilder {
+        ExecutorBuilder {
+            max_blocking_threads: DEFAULT_MAX_BLOCKING_THREADS,
+            blocking_keep_alive: DEFAULT_BLOCKING_KEEP_ALIVE,
+            timer_backend: TimerBackend::Epoll,
+            clock: Rc::new(SystemClock),
+        }
     }
 
     pub fn current() -> Executor {
-        EXECUTOR.with(|executor| executor.borrow().clone().unwrap())
+        Executor {
+            inner: EXECUTOR.with(|executor| executor.borrow().upgrade().unwrap()),
+        }
     }
 
     pub fn wake_on_activity(
@@ -141,79 +447,216 @@ impl Executor {
                 .borrow_mut()
                 .epoll
                 .add(fd, operation, || {})?,
+            _not_send: PhantomData,
         })
     }
 
+    /// Returns the current time according to the executor's clock.
+    pub fn now(&self) -> Instant {
+        self.inner.borrow().clock.now()
+    }
+
     pub fn wake_at_time(&self, time: Instant) -> TimeoutWakeHandle {
-        let id = Executor::current().inner.borrow().last_timer_id;
-        Executor::current().inner.borrow_mut().last_timer_id += 1;
-        Executor::current()
-            .inner
-            .borrow_mut()
-            .timers
-            .insert(id, (time, None));
-        TimeoutWakeHandle { id }
+        TimeoutWakeHandle {
+            id: Executor::current().inner.borrow_mut().timers.insert(time),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Sets a function to be called with the payload of every panic caught in a spawned task.
+    /// The panic is still reported through the task's `JoinHandle` afterwards.
+    #[allow(unused)]
+    pub fn set_panic_hook(&self, hook: impl Fn(&(dyn Any + Send)) + 'static) {
+        self.inner.borrow_mut().panic_hook = Some(Rc::new(hook));
     }
 
-    pub fn spawn<F>(&self, future: F) -> BoxResult<()>
+    /// Spawns a task. A panic inside the task drops it, along with everything it holds,
+    /// and is reported through the panic hook and the returned `JoinHandle`.
+    pub fn spawn<F, T>(&self, future: F) -> JoinHandle<T>
     where
-        F: Future<Output = ()> + 'static,
+        F: Future<Output = T> + 'static,
+        T: 'static,
     {
-        self.inner.borrow().queue.borrow_mut().push_back(Task {
-            future: Box::pin(future),
+        let (join_sender, join_handle) = join_handle::channel();
+        let abort_state = join_sender.abort_state();
+        self.spawn_task(Box::pin(task_future(future, join_sender)), abort_state);
+        join_handle
+    }
+
+    /// Spawns a future produced by `task_future`, which completes its `JoinHandle` by itself.
+    pub fn spawn_task(
+        &self,
+        future: Pin<Box<dyn Future<Output = ()>>>,
+        abort_state: Arc<AbortState>,
+    ) {
+        let id = self.inner.borrow().last_task_id;
+        self.inner.borrow_mut().last_task_id += 1;
+        let waker = Arc::new(TaskWaker {
+            id,
+            scheduled: AtomicBool::new(true),
+            run_queue: self.inner.borrow().run_queue.clone(),
         });
-        Ok(())
+        abort_state.set_waker(&waker.clone().into());
+        let task = Task {
+            future,
+            waker,
+            abort_state,
+        };
+        self.inner.borrow().tasks.borrow_mut().insert(id, task);
+        self.inner.borrow().run_queue.push(id);
+    }
+
+    /// Makes this the executor returned by `Executor::current` on this thread.
+    fn enter(&self) {
+        EXECUTOR.with(|executor| executor.replace(Rc::downgrade(&self.inner)));
     }
 
+    /// Returns whether some task is woken and waiting to be polled.
+    pub fn has_runnable_tasks(&self) -> bool {
+        !self.inner.borrow().run_queue.is_empty()
+    }
+
+    /// Drops every task spawned on the executor, as if each of them was aborted.
+    pub fn abort_all(&self) {
+        self.enter();
+        let tasks = drop_temporaries!(self.inner.borrow().tasks.replace(HashMap::new()));
+        drop(tasks);
+    }
+
+    /// Runs `f` on a thread of the blocking pool, so that it does not stall the executor.
+    /// The returned `JoinHandle` resolves once `f` returns.
+    #[allow(unused)]
+    pub fn spawn_blocking<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.inner.borrow().blocking_pool.spawn(f)
+    }
+
+    #[allow(unused)]
     pub fn run(&self) -> BoxResult<()> {
         loop {
-            while let Some(mut task) =
-                drop_temporaries!(self.inner.borrow().queue.borrow_mut().pop_front())
-            {
-                let task_waker = Arc::new(TaskWaker {
-                    task: SendWrapper::new(RefCell::new(None)),
-                });
-                EXECUTOR.with(|executor| executor.borrow_mut().replace(self.clone()));
-                WAKE_CALLED.with(|wake_called| wake_called.replace(false));
-                if let Poll::Pending = task
-                    .future
-                    .as_mut()
-                    .poll(&mut Context::from_waker(&task_waker.clone().into()))
+            self.poll_tasks();
+            self.wait(None)?;
+        }
+    }
+
+    /// Runs the executor until `future` completes and returns its output. Tasks spawned in the
+    /// meantime make progress as well, but are left in place once `future` is done, until they
+    /// are run again or dropped along with the executor.
+    #[allow(unused)]
+    pub fn block_on<F>(&self, future: F) -> BoxResult<F::Output>
+    where
+        F: Future,
+    {
+        self.enter();
+        let main_waker = Arc::new(MainWaker {
+            woken: AtomicBool::new(true),
+            run_queue: self.inner.borrow().run_queue.clone(),
+        });
+        let waker = main_waker.clone().into();
+        let mut future = Box::pin(future);
+        loop {
+            if main_waker.woken.swap(false, Ordering::SeqCst) {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(&waker))
                 {
-                    if WAKE_CALLED.with(|wake_called| wake_called.take()) {
-                        self.inner.borrow().queue.borrow_mut().push_back(task);
-                    } else {
-                        task_waker.task.borrow_mut().replace(task);
-                    }
+                    return Ok(output);
                 }
             }
-            let timeout = if self.inner.borrow().timers.is_empty() {
-                None
-            } else {
-                Some(
-                    self.inner
-                        .borrow()
-                        .timers
-                        .values()
-                        .map(|(time, _)| time.checked_duration_since(Instant::now()))
-                        .min()
-                        .flatten()
-                        .unwrap_or_else(Duration::zero),
-                )
+            self.poll_tasks();
+            self.wait(Some(&main_waker.woken))?;
+        }
+    }
+
+    /// Runs the executor until there is nothing left that could make progress: no tasks are
+    /// queued, no timers are pending, no FDs are being waited on and no closures are running on
+    /// the blocking pool.
+    #[allow(unused)]
+    pub fn run_until_idle(&self) -> BoxResult<()> {
+        loop {
+            self.poll_tasks();
+            if self.is_idle() {
+                return Ok(());
+            }
+            self.wait(None)?;
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        let inner = self.inner.borrow();
+        // The pool is checked first, as a finished job wakes its task before it stops counting.
+        inner.blocking_pool.in_flight() == 0
+            && inner.run_queue.is_empty()
+            && inner.timers.is_empty()
+            && inner.epoll.len() == inner.internal_epoll_entries
+    }
+
+    fn poll_tasks(&self) {
+        self.enter();
+        let run_queue = self.inner.borrow().run_queue.clone();
+        while let Some(id) = run_queue.pop() {
+            let task = drop_temporaries!(self.inner.borrow().tasks.borrow_mut().remove(&id));
+            let mut task = match task {
+                Some(task) => task,
+                None => continue,
+            };
+            if task.abort_state.is_aborted() {
+                continue;
+            }
+            task.waker.scheduled.store(false, Ordering::SeqCst);
+            let waker = task.waker.clone().into();
+            if let Poll::Pending = task.future.as_mut().poll(&mut Context::from_waker(&waker)) {
+                self.inner.borrow().tasks.borrow_mut().insert(id, task);
+            }
+        }
+    }
+
+    /// Waits for FD activity or the nearest timer and wakes the corresponding futures. Returns
+    /// immediately if some task, or the future passed to `block_on`, is already woken.
+    fn wait(&self, main_woken: Option<&AtomicBool>) -> BoxResult<()> {
+        let run_queue = self.inner.borrow().run_queue.clone();
+        run_queue.sleeping.store(true, Ordering::SeqCst);
+        let main_woken = main_woken.map_or(false, |woken| woken.load(Ordering::SeqCst));
+        let timeout = if main_woken || !run_queue.is_empty() {
+            Some(Duration::zero())
+        } else {
+            let time = drop_temporaries!(self.inner.borrow_mut().timers.next_time());
+            let clock = self.inner.borrow().clock.clone();
+            let timeout = match time {
+                Some(time) if time > clock.now() => clock.timeout_until(time),
+                Some(_) => Some(Duration::zero()),
+                None => None,
             };
-            self.inner.borrow().epoll.wait(timeout)?;
-            let mut to_remove = Vec::new();
-            for (id, (time, waker)) in &self.inner.borrow().timers {
-                if Instant::now() >= *time {
-                    if let Some(waker) = waker {
-                        waker.wake_by_ref();
+            match &self.inner.borrow().timer_fd_timer {
+                Some(timer_fd_timer) if timeout != Some(Duration::zero()) => {
+                    match (time, timeout) {
+                        (Some(time), Some(timeout)) => timer_fd_timer.arm(time, timeout)?,
+                        _ => timer_fd_timer.disarm()?,
                     }
-                    to_remove.push(*id);
+                    None
                 }
+                _ => timeout,
             }
-            for id in to_remove {
-                self.inner.borrow_mut().timers.remove(&id);
-            }
+        };
+        let result = self.inner.borrow().epoll.wait(timeout);
+        run_queue.sleeping.store(false, Ordering::SeqCst);
+        result?;
+        let now = self.now();
+        let wakers = drop_temporaries!(self.inner.borrow_mut().timers.expire(now));
+        for waker in wakers {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        // The last handle drops the remaining tasks while the executor is still current, as the
+        // wake handles they hold unregister themselves through `Executor::current`.
+        if Rc::strong_count(&self.inner) == 1 {
+            self.abort_all();
         }
     }
 }
@@ -0,0 +1,186 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+
+#[derive(Debug)]
+pub enum JoinError {
+    Cancelled,
+    Panicked(Box<dyn Any + Send>),
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => write!(f, "task was cancelled"),
+            JoinError::Panicked(payload) => match panic_message(payload.as_ref()) {
+                Some(message) => write!(f, "task panicked: {}", message),
+                None => write!(f, "task panicked"),
+            },
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+pub fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        Some(message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        Some(message)
+    } else {
+        None
+    }
+}
+
+struct JoinState<T> {
+    completed: bool,
+    output: Option<Result<T, JoinError>>,
+    waker: Option<Waker>,
+}
+
+/// Cancellation flag shared between a task and its `JoinHandle`. The executor checks it every
+/// time the task is scheduled and drops the task's future instead of polling it once it is set.
+pub struct AbortState {
+    aborted: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl AbortState {
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    pub fn set_waker(&self, waker: &Waker) {
+        self.waker.lock().unwrap().replace(waker.clone());
+    }
+
+    fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+pub fn channel<T>() -> (JoinSender<T>, JoinHandle<T>) {
+    let state = Arc::new(Mutex::new(JoinState {
+        completed: false,
+        output: None,
+        waker: None,
+    }));
+    let abort_state = Arc::new(AbortState {
+        aborted: AtomicBool::new(false),
+        waker: Mutex::new(None),
+    });
+    (
+        JoinSender {
+            state: state.clone(),
+            abort_state: abort_state.clone(),
+        },
+        JoinHandle {
+            state,
+            abort_state,
+            abort_on_drop: false,
+        },
+    )
+}
+
+/// Completing side of a `JoinHandle`, owned by the task itself. If it is dropped before
+/// `send` or `fail` is called, the task never finished and the handle resolves to
+/// `JoinError::Cancelled`.
+pub struct JoinSender<T> {
+    state: Arc<Mutex<JoinState<T>>>,
+    abort_state: Arc<AbortState>,
+}
+
+impl<T> JoinSender<T> {
+    pub fn abort_state(&self) -> Arc<AbortState> {
+        self.abort_state.clone()
+    }
+
+    pub fn send(self, output: T) {
+        self.complete(Ok(output));
+    }
+
+    pub fn fail(self, err: JoinError) {
+        self.complete(Err(err));
+    }
+
+    fn complete(&self, output: Result<T, JoinError>) {
+        let mut state = self.state.lock().unwrap();
+        if !state.completed {
+            state.completed = true;
+            state.output = Some(output);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Drop for JoinSender<T> {
+    fn drop(&mut self) {
+        self.complete(Err(JoinError::Cancelled));
+    }
+}
+
+/// Awaits the output of a spawned task. Dropping the handle detaches the task, unless
+/// `abort_on_drop` was requested, in which case the task is aborted as well.
+pub struct JoinHandle<T> {
+    state: Arc<Mutex<JoinState<T>>>,
+    abort_state: Arc<AbortState>,
+    abort_on_drop: bool,
+}
+
+impl<T> JoinHandle<T> {
+    /// Drops the task's future at its next scheduling point, releasing everything it holds.
+    /// Awaiting the handle afterwards yields `JoinError::Cancelled`, unless the task has
+    /// already finished.
+    pub fn abort(&self) {
+        self.abort_state.abort();
+    }
+
+    /// Returns whether the task has finished, including by panicking or being aborted, so that
+    /// awaiting the handle would not block.
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().unwrap().completed
+    }
+
+    #[allow(unused)]
+    pub fn abort_on_drop(mut self) -> JoinHandle<T> {
+        self.abort_on_drop = true;
+        self
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if self.abort_on_drop {
+            self.abort();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.waker.replace(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
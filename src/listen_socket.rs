@@ -1,11 +1,9 @@
 use crate::types::*;
-use nix::sys::socket::accept4;
 use nix::sys::socket::bind;
 use nix::sys::socket::listen;
 use nix::sys::socket::setsockopt;
 use nix::sys::socket::socket;
 use nix::sys::socket::sockopt;
-use nix::sys::socket::AddressFamily;
 use nix::sys::socket::InetAddr;
 use nix::sys::socket::SockAddr;
 use nix::sys::socket::SockFlag;
@@ -13,35 +11,62 @@ use nix::sys::socket::SockType;
 use nix::unistd::close;
 use std::net::SocketAddr;
 
+/// The kernel caps the backlog at `net.core.somaxconn`, so this listens with the maximum.
+pub const DEFAULT_BACKLOG: usize = usize::max_value();
+
 pub struct ListenSocket {
     fd: RawFd,
 }
 
 impl ListenSocket {
+    /// Binds a socket of the family of `addr`. An IPv6 socket also accepts IPv4 connections,
+    /// with IPv4-mapped peer addresses, so that binding `[::]` listens on both protocols.
+    #[allow(unused)]
     pub fn bind<A: Into<SocketAddr>>(addr: A) -> BoxResult<ListenSocket> {
+        ListenSocket::bind_with(addr, DEFAULT_BACKLOG, |_| Ok(()))
+    }
+
+    /// Like `bind`, but listens with the given `backlog`, and calls `configure` with the socket
+    /// before binding it, to set further options, which may override the defaults.
+    pub fn bind_with<A, F>(addr: A, backlog: usize, configure: F) -> BoxResult<ListenSocket>
+    where
+        A: Into<SocketAddr>,
+        F: FnOnce(RawFd) -> BoxResult<()>,
+    {
+        let addr = addr.into();
         let listen_socket = ListenSocket {
             fd: socket(
-                AddressFamily::Inet,
+                address_family(&addr),
                 SockType::Stream,
                 SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
                 None,
             )?,
         };
         setsockopt(listen_socket.fd, sockopt::ReuseAddr, &true)?;
-        bind(
-            listen_socket.fd,
-            &SockAddr::Inet(InetAddr::from_std(&addr.into())),
-        )?;
-        listen(listen_socket.fd, usize::max_value())?;
+        if let SocketAddr::V6(_) = addr {
+            // Set explicitly, as the default depends on the `net.ipv6.bindv6only` sysctl.
+            set_int_sockopt(listen_socket.fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, 0)?;
+        }
+        configure(listen_socket.fd)?;
+        bind(listen_socket.fd, &SockAddr::Inet(InetAddr::from_std(&addr)))?;
+        listen(listen_socket.fd, backlog)?;
         Ok(listen_socket)
     }
 
+    /// Takes ownership of a listening non-blocking socket, such as one passed by another process
+    /// with `AsyncUnixStream::send_with_fds`, for handing over a server without downtime.
+    #[allow(unused)]
+    pub fn from_fd(fd: RawFd) -> ListenSocket {
+        ListenSocket { fd }
+    }
+
     pub fn fd(&self) -> RawFd {
         self.fd
     }
 
-    pub fn accept(&mut self) -> nix::Result<RawFd> {
-        accept4(self.fd, SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC)
+    /// Accepts a connection, returning its non-blocking socket and the address of the peer.
+    pub fn accept(&mut self) -> nix::Result<(RawFd, SockAddr)> {
+        accept_socket(self.fd)
     }
 }
 
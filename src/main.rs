@@ -5,58 +5,116 @@
 
 #[macro_use]
 mod types;
+mod accept;
+mod async_alarm;
 mod async_buf_reader;
 mod async_file;
+mod async_process;
 mod async_sleep;
 mod async_tcp_listener;
+mod async_tcp_stream;
+mod async_timeout;
+mod async_unix_listener;
+mod async_unix_stream;
+mod blocking_pool;
+mod cancellation_token;
+mod clock;
 mod epoll;
 mod executor;
+mod join_handle;
 mod listen_socket;
+mod multi_thread_executor;
+mod scheduler;
+mod signal;
+mod task_group;
+mod timer_heap;
+mod unix_listen_socket;
 
 use crate::async_buf_reader::AsyncBufReader;
-use crate::async_file::AsyncFile;
 use crate::async_sleep::async_sleep;
 use crate::async_tcp_listener::AsyncTcpListener;
+use crate::async_tcp_stream::AsyncTcpStream;
+use crate::cancellation_token::CancellationToken;
 use crate::executor::Executor;
+use crate::join_handle::JoinHandle;
 use crate::types::*;
+use nix::sys::signal::Signal;
+use std::net::SocketAddr;
 use std::time::Duration;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let executor = Executor::new()?;
-    executor.spawn(async {
-        let mut async_tcp_listener = AsyncTcpListener::bind(([0, 0, 0, 0], 1234)).unwrap();
-        loop {
-            let socket = async_tcp_listener.incoming().await.unwrap();
-            Executor::current()
-                .spawn(async move {
-                    println!("Client {} connected", socket);
-                    let result: BoxResult<()> = (async move || {
-                        let mut async_file = AsyncFile::from_fd(socket);
-                        while let Some(line) = drop_temporaries!(
-                            AsyncBufReader::new(&mut async_file).read_line().await?
-                        ) {
-                            println!("Client {} says: {}", socket, &line);
-                            match &line.split(' ').collect::<Vec<_>>()[..] {
-                                ["quit"] => break,
-                                ["echo", value] => async_writeln!(async_file, "{}", value).await?,
-                                ["sleep", value] => {
-                                    async_sleep(Duration::from_millis(value.parse()?)).await;
-                                    async_writeln!(async_file, "done sleeping").await?;
-                                }
-                                _ => async_writeln!(async_file, "unknown command").await?,
-                            }
-                        }
-                        println!("Client {} disconnected", socket);
-                        Ok(())
-                    })()
-                    .await;
-                    if let Err(err) = result {
-                        println!("Client {} errored: {:?}", socket, err);
-                    }
-                })
-                .unwrap();
+fn cancel_on_signal(signal: Signal, shutdown: &CancellationToken) -> BoxResult<()> {
+    let mut signal_stream = signal::signal(signal)?;
+    let shutdown = shutdown.clone();
+    Executor::current().spawn(async move {
+        if signal_stream.recv().await.is_ok() {
+            println!("Received {}", signal);
+            shutdown.cancel();
+        }
+    });
+    Ok(())
+}
+
+async fn handle_client(
+    mut async_tcp_stream: AsyncTcpStream,
+    peer_addr: SocketAddr,
+    shutdown: CancellationToken,
+) {
+    println!("Client {} connected", peer_addr);
+    let result: BoxResult<()> = (async move || {
+        // On shutdown, the command that is being handled is finished, but no new ones are read.
+        while let Some(line) = drop_temporaries!(shutdown
+            .run_until_cancelled(AsyncBufReader::new(&mut async_tcp_stream).read_line())
+            .await
+            .unwrap_or(Ok(None))?)
+        {
+            println!("Client {} says: {}", peer_addr, &line);
+            match &line.split(' ').collect::<Vec<_>>()[..] {
+                ["quit"] => break,
+                ["echo", value] => async_writeln!(async_tcp_stream, "{}", value).await?,
+                ["sleep", value] => {
+                    async_sleep(Duration::from_millis(value.parse()?)).await;
+                    async_writeln!(async_tcp_stream, "done sleeping").await?;
+                }
+                _ => async_writeln!(async_tcp_stream, "unknown command").await?,
+            }
         }
-    })?;
-    executor.run()?;
+        println!("Client {} disconnected", peer_addr);
+        Ok(())
+    })()
+    .await;
+    if let Err(err) = result {
+        println!("Client {} errored: {:?}", peer_addr, err);
+    }
+}
+
+async fn serve() -> BoxResult<()> {
+    let shutdown = CancellationToken::new();
+    cancel_on_signal(Signal::SIGINT, &shutdown)?;
+    cancel_on_signal(Signal::SIGTERM, &shutdown)?;
+    let mut async_tcp_listener = AsyncTcpListener::bind(([0, 0, 0, 0], 1234))?;
+    let mut clients: Vec<JoinHandle<()>> = Vec::new();
+    while let Some(connection) = shutdown
+        .run_until_cancelled(async_tcp_listener.accept())
+        .await
+    {
+        let (async_tcp_stream, peer_addr) = connection?;
+        clients.retain(|client| !client.is_finished());
+        clients.push(Executor::current().spawn(handle_client(
+            async_tcp_stream,
+            peer_addr,
+            shutdown.clone(),
+        )));
+    }
+    drop(async_tcp_listener);
+    clients.retain(|client| !client.is_finished());
+    println!("Shutting down, waiting for {} clients", clients.len());
+    for client in clients {
+        let _ = client.await;
+    }
     Ok(())
 }
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let executor = Executor::new()?;
+    executor.block_on(serve())?
+}
@@ -0,0 +1,311 @@
+use crate::executor::task_future;
+use crate::executor::Executor;
+use crate::join_handle;
+use crate::join_handle::AbortState;
+use crate::join_handle::JoinHandle;
+use crate::types::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Wake;
+use std::task::Waker;
+use std::thread;
+use std::thread::Thread;
+
+thread_local! {
+    static WORKER: RefCell<Option<(Arc<Shared>, usize)>> = RefCell::new(None);
+}
+
+type CreateFuture = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()>>> + Send>;
+
+/// A task waiting in the queues, which creates its future on the worker that takes it.
+struct SendTask {
+    create: CreateFuture,
+    abort_state: Arc<AbortState>,
+}
+
+struct Worker {
+    queue: Mutex<VecDeque<SendTask>>,
+    waker: Mutex<Option<Waker>>,
+}
+
+struct Shared {
+    injector: Mutex<VecDeque<SendTask>>,
+    workers: Vec<Worker>,
+    shutdown: AtomicBool,
+    threads: Mutex<Vec<thread::JoinHandle<()>>>,
+}
+
+impl Shared {
+    fn find_task(&self, index: usize) -> Option<SendTask> {
+        if let Some(task) = self.workers[index].queue.lock().unwrap().pop_front() {
+            return Some(task);
+        }
+        if let Some(task) = self.injector.lock().unwrap().pop_front() {
+            return Some(task);
+        }
+        for offset in 1..self.workers.len() {
+            let victim = &self.workers[(index + offset) % self.workers.len()];
+            let mut stolen = {
+                let mut victim_queue = victim.queue.lock().unwrap();
+                let count = (victim_queue.len() + 1) / 2;
+                let at = victim_queue.len() - count;
+                victim_queue.split_off(at)
+            };
+            if let Some(task) = stolen.pop_front() {
+                self.workers[index].queue.lock().unwrap().extend(stolen);
+                return Some(task);
+            }
+        }
+        None
+    }
+
+    fn wake_worker(&self, index: usize) -> bool {
+        let waker = self.workers[index].waker.lock().unwrap().take();
+        match waker {
+            Some(waker) => {
+                waker.wake();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn wake_idle_worker(&self) {
+        for index in 0..self.workers.len() {
+            if self.wake_worker(index) {
+                break;
+            }
+        }
+    }
+
+    fn begin_shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        for index in 0..self.workers.len() {
+            self.wake_worker(index);
+        }
+    }
+
+    fn join_threads(&self) {
+        let threads = self.threads.lock().unwrap().drain(..).collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    fn is_current_worker(self: &Arc<Self>) -> bool {
+        WORKER.with(|worker| match &*worker.borrow() {
+            Some((shared, _)) => Arc::ptr_eq(shared, self),
+            None => false,
+        })
+    }
+}
+
+/// Held by every `MultiThreadExecutor` handle, but not by the worker threads, so that the
+/// runtime is shut down once the last handle is dropped.
+struct HandleGuard {
+    shared: Arc<Shared>,
+}
+
+impl Drop for HandleGuard {
+    fn drop(&mut self) {
+        self.shared.begin_shutdown();
+        // A worker thread can't wait for itself; it exits once its current poll returns.
+        if !self.shared.is_current_worker() {
+            self.shared.join_threads();
+        }
+    }
+}
+
+/// Moves tasks from the queues of the runtime to the executor of a worker thread. Each worker
+/// thread runs its own `Executor` with its own reactor, so once a task has been polled, its FD and
+/// timer registrations belong to that worker and it stays there. Tasks that have not been
+/// polled yet can be stolen by any idle worker.
+struct WorkerFuture {
+    shared: Arc<Shared>,
+    index: usize,
+}
+
+impl Future for WorkerFuture {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.shared.shutdown.load(Ordering::SeqCst) {
+            return Poll::Ready(());
+        }
+        if Executor::current().has_runnable_tasks() {
+            // Take new work only once the tasks already on this worker are idle, so that the
+            // queue stays available to idle workers in the meantime.
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let mut task = self.shared.find_task(self.index);
+        if task.is_none() {
+            self.shared.workers[self.index]
+                .waker
+                .lock()
+                .unwrap()
+                .replace(cx.waker().clone());
+            if self.shared.shutdown.load(Ordering::SeqCst) {
+                return Poll::Ready(());
+            }
+            task = self.shared.find_task(self.index);
+        }
+        if let Some(task) = task {
+            Executor::current().spawn_task((task.create)(), task.abort_state);
+            if !self.shared.workers[self.index]
+                .queue
+                .lock()
+                .unwrap()
+                .is_empty()
+            {
+                self.shared.wake_idle_worker();
+            }
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+struct ThreadWaker {
+    thread: Thread,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.thread.unpark();
+    }
+}
+
+/// Runtime with a fixed number of worker threads. Cloning it is cheap and gives another handle
+/// to the same runtime, which is shut down when the last handle is dropped.
+///
+/// Work is only balanced when tasks are handed out: a worker takes a task from the queues once
+/// its own tasks are idle, and from then on runs it until it completes, as the FDs and timers
+/// it waits on are registered with that worker's reactor. A long-lived task, such as a busy
+/// connection, is therefore never moved off a worker that has become overloaded.
+///
+/// As these registrations are not `Send`, neither is a future holding one. Such futures are
+/// started with `spawn_pinned`, which sends a function creating the future to the worker.
+#[allow(unused)]
+#[derive(Clone)]
+pub struct MultiThreadExecutor {
+    shared: Arc<Shared>,
+    _guard: Arc<HandleGuard>,
+}
+
+#[allow(unused)]
+impl MultiThreadExecutor {
+    pub fn new(worker_threads: usize) -> BoxResult<MultiThreadExecutor> {
+        assert!(worker_threads > 0);
+        let shared = Arc::new(Shared {
+            injector: Mutex::new(VecDeque::new()),
+            workers: (0..worker_threads)
+                .map(|_| Worker {
+                    queue: Mutex::new(VecDeque::new()),
+                    waker: Mutex::new(None),
+                })
+                .collect(),
+            shutdown: AtomicBool::new(false),
+            threads: Mutex::new(Vec::new()),
+        });
+        for index in 0..worker_threads {
+            let worker_shared = shared.clone();
+            let thread = thread::Builder::new()
+                .name(format!("worker-{}", index))
+                .spawn(move || {
+                    WORKER.with(|worker| worker.replace(Some((worker_shared.clone(), index))));
+                    let executor = Executor::new().expect("failed to create worker executor");
+                    executor
+                        .block_on(WorkerFuture {
+                            shared: worker_shared,
+                            index,
+                        })
+                        .expect("worker executor failed");
+                })?;
+            shared.threads.lock().unwrap().push(thread);
+        }
+        Ok(MultiThreadExecutor {
+            _guard: Arc::new(HandleGuard {
+                shared: shared.clone(),
+            }),
+            shared,
+        })
+    }
+
+    /// Spawns a task on the runtime. When called from one of its worker threads, the task is
+    /// queued on that worker first, otherwise it goes to the shared queue.
+    pub fn spawn<F, T>(&self, future: F) -> JoinHandle<T>
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_pinned(move || future)
+    }
+
+    /// Spawns a task whose future is created by `create` on the worker that takes it, and
+    /// stays there. Unlike with `spawn`, the future doesn't have to be `Send`, so it can wait
+    /// on FDs and timers.
+    pub fn spawn_pinned<C, F, T>(&self, create: C) -> JoinHandle<T>
+    where
+        C: FnOnce() -> F + Send + 'static,
+        F: Future<Output = T> + 'static,
+        T: Send + 'static,
+    {
+        let (join_sender, join_handle) = join_handle::channel();
+        let task = SendTask {
+            abort_state: join_sender.abort_state(),
+            create: Box::new(move || Box::pin(task_future(create(), join_sender))),
+        };
+        let local_index = WORKER.with(|worker| match &*worker.borrow() {
+            Some((shared, index)) if Arc::ptr_eq(shared, &self.shared) => Some(*index),
+            _ => None,
+        });
+        match local_index {
+            Some(index) => {
+                self.shared.workers[index]
+                    .queue
+                    .lock()
+                    .unwrap()
+                    .push_back(task);
+                self.shared.wake_worker(index);
+            }
+            None => self.shared.injector.lock().unwrap().push_back(task),
+        }
+        self.shared.wake_idle_worker();
+        join_handle
+    }
+
+    /// Runs `future` on the runtime and blocks the current thread until it completes.
+    pub fn block_on<F, T>(&self, future: F) -> BoxResult<T>
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut join_handle = self.spawn(future);
+        let waker = Arc::new(ThreadWaker {
+            thread: thread::current(),
+        })
+        .into();
+        loop {
+            match Pin::new(&mut join_handle).poll(&mut Context::from_waker(&waker)) {
+                Poll::Ready(output) => return Ok(output?),
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    /// Stops the worker threads and waits for them to exit. Tasks that have not finished yet
+    /// are dropped. Must not be called from a worker thread.
+    pub fn shutdown(&self) {
+        self.shared.begin_shutdown();
+        self.shared.join_threads();
+    }
+}
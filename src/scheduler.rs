@@ -0,0 +1,557 @@
+use crate::async_alarm::sleep_until_system_time;
+use crate::async_sleep::async_sleep;
+use crate::async_sleep::interval_at;
+use crate::async_sleep::MissedTickBehavior;
+use crate::executor::Executor;
+use crate::join_handle::JoinHandle;
+use crate::types::*;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// How many years ahead `CronExpression::next_after` looks before giving up, which is enough
+/// for expressions such as `0 0 29 2 *` that only match in leap years.
+const CRON_SEARCH_YEARS: libc::c_int = 8;
+
+#[derive(Debug)]
+pub struct InvalidCronExpression {
+    expression: String,
+}
+
+impl fmt::Display for InvalidCronExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid cron expression: {:?}", self.expression)
+    }
+}
+
+impl Error for InvalidCronExpression {}
+
+/// Set of the values allowed in one field of a cron expression.
+#[derive(Clone, Copy)]
+struct CronField {
+    values: u64,
+    /// Whether the field does not start with `*`, which matters for the day fields.
+    restricted: bool,
+}
+
+impl CronField {
+    fn parse(field: &str, min: u32, max: u32) -> Option<CronField> {
+        let mut values = 0;
+        for part in field.split(',') {
+            let (range, step) = match part.find('/') {
+                Some(index) => (&part[..index], part[index + 1..].parse().ok()?),
+                None => (part, 1),
+            };
+            let (start, end) = if range == "*" {
+                (min, max)
+            } else {
+                match range.find('-') {
+                    Some(index) => (
+                        range[..index].parse().ok()?,
+                        range[index + 1..].parse().ok()?,
+                    ),
+                    None if part.contains('/') => (range.parse().ok()?, max),
+                    None => {
+                        let value = range.parse().ok()?;
+                        (value, value)
+                    }
+                }
+            };
+            if step == 0 || start < min || end > max || start > end {
+                return None;
+            }
+            for value in (start..=end).step_by(step as usize) {
+                values |= 1 << value;
+            }
+        }
+        Some(CronField {
+            values,
+            // Like in Vixie cron, `*/2` still counts as unrestricted.
+            restricted: !field.starts_with('*'),
+        })
+    }
+
+    fn contains(&self, value: libc::c_int) -> bool {
+        self.values & (1 << value) != 0
+    }
+}
+
+/// Standard five-field cron expression: minute, hour, day of month, month and day of week,
+/// each being `*` or a comma-separated list of values, `a-b` ranges and `/step` suffixes.
+/// Day of week 0 and 7 are both Sunday. If both day fields are restricted, that is, neither
+/// starts with `*`, a day matches when either of them does. Times are in the local time zone.
+#[derive(Clone, Copy)]
+pub struct CronExpression {
+    minutes: CronField,
+    hours: CronField,
+    days_of_month: CronField,
+    months: CronField,
+    days_of_week: CronField,
+}
+
+#[allow(unused)]
+impl CronExpression {
+    pub fn parse(expression: &str) -> BoxResult<CronExpression> {
+        let invalid = || InvalidCronExpression {
+            expression: expression.to_string(),
+        };
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(Box::new(invalid()));
+        }
+        let parse_field =
+            |index: usize, min, max| CronField::parse(fields[index], min, max).ok_or_else(invalid);
+        let mut days_of_week = parse_field(4, 0, 7)?;
+        if days_of_week.contains(7) {
+            days_of_week.values |= 1;
+        }
+        Ok(CronExpression {
+            minutes: parse_field(0, 0, 59)?,
+            hours: parse_field(1, 0, 23)?,
+            days_of_month: parse_field(2, 1, 31)?,
+            months: parse_field(3, 1, 12)?,
+            days_of_week,
+        })
+    }
+
+    /// Returns the first time matching the expression that is strictly after `time`.
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let mut t = time.duration_since(UNIX_EPOCH).ok()?.as_secs() as libc::time_t;
+        let mut tm = local_time(t)?;
+        let last_year = tm.tm_year + CRON_SEARCH_YEARS;
+        tm.tm_sec = 0;
+        tm.tm_min += 1;
+        t = make_time(&mut tm)?;
+        loop {
+            let mut tm = local_time(t)?;
+            if tm.tm_year > last_year {
+                return None;
+            }
+            // Advance the first field that does not match to its next value, resetting the
+            // fields below it, and let `mktime` carry the overflow.
+            if !self.months.contains(tm.tm_mon + 1) {
+                tm.tm_mon += 1;
+                tm.tm_mday = 1;
+                tm.tm_hour = 0;
+                tm.tm_min = 0;
+            } else if !self.day_matches(&tm) {
+                tm.tm_mday += 1;
+                tm.tm_hour = 0;
+                tm.tm_min = 0;
+            } else if !self.hours.contains(tm.tm_hour) {
+                tm.tm_hour += 1;
+                tm.tm_min = 0;
+            } else if !self.minutes.contains(tm.tm_min) {
+                tm.tm_min += 1;
+            } else {
+                return Some(UNIX_EPOCH + Duration::from_secs(t as u64));
+            }
+            let next = make_time(&mut tm)?;
+            // Around daylight saving time transitions `mktime` may move backwards.
+            t = if next > t { next } else { t + 60 };
+        }
+    }
+
+    fn day_matches(&self, tm: &libc::tm) -> bool {
+        let day_of_month = self.days_of_month.contains(tm.tm_mday);
+        let day_of_week = self.days_of_week.contains(tm.tm_wday);
+        if self.days_of_month.restricted && self.days_of_week.restricted {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        }
+    }
+}
+
+fn local_time(t: libc::time_t) -> Option<libc::tm> {
+    let mut tm = unsafe { mem::zeroed() };
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        None
+    } else {
+        Some(tm)
+    }
+}
+
+fn make_time(tm: &mut libc::tm) -> Option<libc::time_t> {
+    tm.tm_isdst = -1;
+    match unsafe { libc::mktime(tm) } {
+        -1 => None,
+        t => Some(t),
+    }
+}
+
+/// When a scheduled job fires.
+#[allow(unused)]
+#[derive(Clone, Copy)]
+pub enum Schedule {
+    /// At the wall-clock times matching the expression, following changes of the system clock.
+    Cron(CronExpression),
+    /// Every period, starting a period from now, regardless of how long the runs take.
+    FixedRate(Duration),
+    /// A period after the previous run has finished, starting a period from now.
+    FixedDelay(Duration),
+}
+
+/// What happens when a job fires while its previous run is still in progress. Runs of a
+/// `Schedule::FixedDelay` job never overlap.
+#[allow(unused)]
+#[derive(Clone, Copy)]
+pub enum OverlapPolicy {
+    /// Drops the new run.
+    Skip,
+    /// Starts the new run once the previous ones have finished.
+    Queue,
+    /// Starts the new run right away.
+    Concurrent,
+}
+
+pub struct ScheduleBuilder {
+    schedule: Schedule,
+    overlap_policy: OverlapPolicy,
+    jitter: Duration,
+}
+
+#[allow(unused)]
+impl ScheduleBuilder {
+    pub fn new(schedule: Schedule) -> ScheduleBuilder {
+        ScheduleBuilder {
+            schedule,
+            overlap_policy: OverlapPolicy::Skip,
+            jitter: Duration::zero(),
+        }
+    }
+
+    pub fn overlap_policy(mut self, overlap_policy: OverlapPolicy) -> ScheduleBuilder {
+        self.overlap_policy = overlap_policy;
+        self
+    }
+
+    /// Delays every run by a random duration below `jitter`, so that jobs of many processes
+    /// with the same schedule do not all start at the same moment.
+    pub fn jitter(mut self, jitter: Duration) -> ScheduleBuilder {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Spawns a task on the current executor that starts a task running `job()` for every
+    /// firing of the schedule. Panics of the runs are reported through the panic hook of the
+    /// executor and do not stop the schedule.
+    pub fn spawn<F, Fut>(self, job: F) -> ScheduleHandle
+    where
+        F: FnMut() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        let cancelled = Rc::new(Cell::new(false));
+        let schedule = self.schedule;
+        let mut runner = Runner {
+            job,
+            overlap_policy: self.overlap_policy,
+            jitter: self.jitter,
+            random_state: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_nanos() as u64)
+                .unwrap_or(0)
+                | 1,
+            cancelled: cancelled.clone(),
+            running: Rc::new(Cell::new(0)),
+            last_run: None,
+        };
+        let join_handle = Executor::current().spawn(async move {
+            match schedule {
+                Schedule::Cron(expression) => {
+                    while let Some(time) = expression.next_after(SystemTime::now()) {
+                        sleep_until_system_time(time).await?;
+                        runner.start_run();
+                    }
+                }
+                Schedule::FixedRate(period) => {
+                    let mut interval = interval_at(Executor::current().now() + period, period);
+                    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                    loop {
+                        interval.tick().await;
+                        runner.start_run();
+                    }
+                }
+                Schedule::FixedDelay(delay) => {
+                    // Every run is awaited before the next one is scheduled, so no policy
+                    // needs to be applied.
+                    runner.overlap_policy = OverlapPolicy::Concurrent;
+                    loop {
+                        async_sleep(delay).await;
+                        if let Some(run) = runner.start_run() {
+                            let _ = run.await;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        });
+        ScheduleHandle {
+            cancelled,
+            join_handle,
+        }
+    }
+}
+
+/// Decrements the number of running runs when a run finishes, panics or is aborted.
+struct RunGuard {
+    running: Rc<Cell<usize>>,
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        self.running.set(self.running.get() - 1);
+    }
+}
+
+struct Runner<F> {
+    job: F,
+    overlap_policy: OverlapPolicy,
+    jitter: Duration,
+    random_state: u64,
+    cancelled: Rc<Cell<bool>>,
+    running: Rc<Cell<usize>>,
+    last_run: Option<JoinHandle<()>>,
+}
+
+impl<F, Fut> Runner<F>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()> + 'static,
+{
+    /// Returns a random duration below `jitter`, using xorshift.
+    fn next_jitter(&mut self) -> Duration {
+        if self.jitter == Duration::zero() {
+            return Duration::zero();
+        }
+        self.random_state ^= self.random_state << 13;
+        self.random_state ^= self.random_state >> 7;
+        self.random_state ^= self.random_state << 17;
+        Duration::from_nanos(self.random_state % self.jitter.as_nanos() as u64)
+    }
+
+    /// Spawns a run unless the overlap policy drops it.
+    fn start_run(&mut self) -> Option<JoinHandle<()>> {
+        if let OverlapPolicy::Skip = self.overlap_policy {
+            if self.running.get() > 0 {
+                return None;
+            }
+        }
+        let previous_run = match self.overlap_policy {
+            OverlapPolicy::Queue => self.last_run.take(),
+            _ => None,
+        };
+        let jitter = self.next_jitter();
+        let cancelled = self.cancelled.clone();
+        let future = (self.job)();
+        self.running.set(self.running.get() + 1);
+        let run_guard = RunGuard {
+            running: self.running.clone(),
+        };
+        let run = Executor::current().spawn(async move {
+            let _run_guard = run_guard;
+            if let Some(previous_run) = previous_run {
+                let _ = previous_run.await;
+            }
+            if jitter > Duration::zero() {
+                async_sleep(jitter).await;
+            }
+            // Runs that were queued or delayed by jitter do not start after cancellation.
+            if !cancelled.get() {
+                future.await;
+            }
+        });
+        match self.overlap_policy {
+            OverlapPolicy::Queue => {
+                self.last_run = Some(run);
+                None
+            }
+            _ => Some(run),
+        }
+    }
+}
+
+/// Handle of a schedule spawned by `ScheduleBuilder::spawn`. Dropping it leaves the schedule
+/// running. Awaiting it returns once the schedule stops, which only happens on its own for cron
+/// expressions that do not match any time anymore, or if waiting for the next firing fails.
+pub struct ScheduleHandle {
+    cancelled: Rc<Cell<bool>>,
+    join_handle: JoinHandle<BoxResult<()>>,
+}
+
+#[allow(unused)]
+impl ScheduleHandle {
+    /// Stops the schedule. Runs that have already started are left to finish.
+    pub fn cancel(&self) {
+        self.cancelled.set(true);
+        self.join_handle.abort();
+    }
+}
+
+impl Future for ScheduleHandle {
+    type Output = BoxResult<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.join_handle).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(Box::new(err))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    extern "C" {
+        fn tzset();
+    }
+
+    /// `TZ` is shared by the whole process, so the tests that depend on it run one at a time.
+    static TIME_ZONE_LOCK: AtomicBool = AtomicBool::new(false);
+
+    struct TimeZoneGuard;
+
+    impl Drop for TimeZoneGuard {
+        fn drop(&mut self) {
+            TIME_ZONE_LOCK.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn set_time_zone(time_zone: &str) -> TimeZoneGuard {
+        while TIME_ZONE_LOCK
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            thread::yield_now();
+        }
+        std::env::set_var("TZ", time_zone);
+        unsafe { tzset() };
+        TimeZoneGuard
+    }
+
+    fn utc(year: i32, month: i32, day: i32, hour: i32, minute: i32) -> SystemTime {
+        let mut tm: libc::tm = unsafe { mem::zeroed() };
+        tm.tm_year = year - 1900;
+        tm.tm_mon = month - 1;
+        tm.tm_mday = day;
+        tm.tm_hour = hour;
+        tm.tm_min = minute;
+        UNIX_EPOCH + Duration::from_secs(unsafe { libc::timegm(&mut tm) } as u64)
+    }
+
+    fn next_after(expression: &str, time: SystemTime) -> Option<SystemTime> {
+        CronExpression::parse(expression).unwrap().next_after(time)
+    }
+
+    fn values(field: &str, min: u32, max: u32) -> Vec<u32> {
+        let field = CronField::parse(field, min, max).unwrap();
+        (min..=max)
+            .filter(|&value| field.contains(value as libc::c_int))
+            .collect()
+    }
+
+    #[test]
+    fn parses_steps_and_ranges() {
+        assert_eq!(values("*/15", 0, 59), vec![0, 15, 30, 45]);
+        assert_eq!(values("10-20/5", 0, 59), vec![10, 15, 20]);
+        assert_eq!(values("5/20", 0, 59), vec![5, 25, 45]);
+        assert_eq!(values("1,3-4,22", 0, 23), vec![1, 3, 4, 22]);
+        assert!(!CronField::parse("*/2", 1, 31).unwrap().restricted);
+        assert!(CronField::parse("1-31", 1, 31).unwrap().restricted);
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for expression in &[
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "* * * *",
+            "* * * * * *",
+        ] {
+            assert!(CronExpression::parse(expression).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn matches_steps_and_ranges() {
+        let _guard = set_time_zone("UTC");
+        assert_eq!(
+            next_after("*/15 9-17 * * *", utc(2024, 1, 1, 17, 50)),
+            Some(utc(2024, 1, 2, 9, 0))
+        );
+        assert_eq!(
+            next_after("*/15 9-17 * * *", utc(2024, 1, 1, 9, 0)),
+            Some(utc(2024, 1, 1, 9, 15))
+        );
+    }
+
+    #[test]
+    fn treats_day_of_week_7_as_sunday() {
+        let _guard = set_time_zone("UTC");
+        // 2024-01-01 is a Monday.
+        assert_eq!(
+            next_after("0 0 * * 7", utc(2024, 1, 1, 0, 0)),
+            Some(utc(2024, 1, 7, 0, 0))
+        );
+    }
+
+    #[test]
+    fn combines_day_fields() {
+        let _guard = set_time_zone("UTC");
+        // Both restricted: the 3rd or a Monday.
+        assert_eq!(
+            next_after("0 0 3 * 1", utc(2024, 1, 1, 0, 0)),
+            Some(utc(2024, 1, 3, 0, 0))
+        );
+        // Day of month starts with `*`: odd days that are Mondays.
+        assert_eq!(
+            next_after("0 0 */2 * 1", utc(2024, 1, 1, 0, 0)),
+            Some(utc(2024, 1, 15, 0, 0))
+        );
+    }
+
+    #[test]
+    fn finds_leap_days() {
+        let _guard = set_time_zone("UTC");
+        assert_eq!(
+            next_after("0 0 29 2 *", utc(2024, 3, 1, 0, 0)),
+            Some(utc(2028, 2, 29, 0, 0))
+        );
+        assert_eq!(next_after("0 0 31 2 *", utc(2024, 1, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn follows_daylight_saving_time() {
+        let _guard = set_time_zone("CET-1CEST,M3.5.0,M10.5.0/3");
+        // Clocks go from 02:00 to 03:00 on 2024-03-31, so the next hour is 03:00 CEST.
+        assert_eq!(
+            next_after("0 * * * *", utc(2024, 3, 31, 0, 30)),
+            Some(utc(2024, 3, 31, 1, 0))
+        );
+        // 03:00 is an hour later in UTC once the clocks go back on 2024-10-27.
+        assert_eq!(
+            next_after("0 3 * * *", utc(2024, 10, 26, 1, 0)),
+            Some(utc(2024, 10, 27, 2, 0))
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use crate::executor::ActivityWakeHandle;
+use crate::executor::Executor;
+use crate::types::*;
+use nix::sys::signal::SigSet;
+use nix::sys::signal::Signal;
+use nix::sys::signalfd::SfdFlags;
+use nix::sys::signalfd::SignalFd;
+use std::future::Future;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+/// Blocks `signal` for the current thread and returns a stream of its deliveries, read from a
+/// signalfd. Threads inherit the signal mask, so this should be called before other threads
+/// are started, or the signal may be delivered to one of them instead, with its default
+/// action. The signal stays blocked after the stream is dropped. Deliveries of a signal that
+/// arrive before the previous one is received are merged into one.
+pub fn signal(signal: Signal) -> BoxResult<SignalStream> {
+    let mut mask = SigSet::empty();
+    mask.add(signal);
+    mask.thread_block()?;
+    let signal_fd = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)?;
+    let activity_wake_handle =
+        Executor::current().wake_on_activity(signal_fd.as_raw_fd(), Operation::READ)?;
+    Ok(SignalStream {
+        signal_fd,
+        activity_wake_handle,
+    })
+}
+
+/// Waits for the first `SIGINT`, as sent by Ctrl-C.
+#[allow(unused)]
+pub async fn ctrl_c() -> BoxResult<()> {
+    signal(Signal::SIGINT)?.recv().await
+}
+
+pub struct SignalStream {
+    signal_fd: SignalFd,
+    activity_wake_handle: ActivityWakeHandle,
+}
+
+impl SignalStream {
+    /// Waits for the next delivery of the signal.
+    pub fn recv(&mut self) -> Recv<'_> {
+        Recv {
+            signal_stream: self,
+        }
+    }
+}
+
+pub struct Recv<'a> {
+    signal_stream: &'a mut SignalStream,
+}
+
+impl<'a> Future for Recv<'a> {
+    type Output = BoxResult<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.signal_stream.signal_fd.read_signal() {
+            Ok(Some(_)) => Poll::Ready(Ok(())),
+            Ok(None) => {
+                self.signal_stream
+                    .activity_wake_handle
+                    .set_waker(cx.waker());
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(Box::new(err))),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Wake;
+use std::task::Waker;
+
+struct Child<'a, E> {
+    future: Pin<Box<dyn Future<Output = Result<(), E>> + 'a>>,
+    waker: Arc<ChildWaker>,
+}
+
+/// Indices of the children that are ready to be polled, along with the waker of the task
+/// awaiting `TaskGroup::join`.
+struct TaskGroupShared {
+    ready: Mutex<Vec<usize>>,
+    waker: Mutex<Option<Waker>>,
+}
+
+struct ChildWaker {
+    index: usize,
+    scheduled: AtomicBool,
+    shared: Arc<TaskGroupShared>,
+}
+
+impl Wake for ChildWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.scheduled.swap(true, Ordering::SeqCst) {
+            self.shared.ready.lock().unwrap().push(self.index);
+        }
+        if let Some(waker) = &*self.shared.waker.lock().unwrap() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+/// Group of child tasks that may borrow from the stack of the task that owns the group. The
+/// children are not spawned on the executor, but polled by `join` within the owning task, so
+/// they only run while it is awaited, and dropping the group cancels the children that have
+/// not finished. The first child to fail cancels all the others.
+pub struct TaskGroup<'a, E> {
+    children: RefCell<Vec<Option<Child<'a, E>>>>,
+    remaining: Cell<usize>,
+    shared: Arc<TaskGroupShared>,
+}
+
+#[allow(unused)]
+impl<'a, E> TaskGroup<'a, E> {
+    pub fn new() -> TaskGroup<'a, E> {
+        TaskGroup {
+            children: RefCell::new(Vec::new()),
+            remaining: Cell::new(0),
+            shared: Arc::new(TaskGroupShared {
+                ready: Mutex::new(Vec::new()),
+                waker: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Adds a child to the group. It is first polled once the group is joined.
+    pub fn spawn(&self, future: impl Future<Output = Result<(), E>> + 'a) {
+        let index = self.children.borrow().len();
+        let child_waker = Arc::new(ChildWaker {
+            index,
+            scheduled: AtomicBool::new(false),
+            shared: self.shared.clone(),
+        });
+        self.children.borrow_mut().push(Some(Child {
+            future: Box::pin(future),
+            waker: child_waker.clone(),
+        }));
+        self.remaining.set(self.remaining.get() + 1);
+        // Schedules the first poll of the child.
+        child_waker.wake_by_ref();
+    }
+
+    /// Drops all the children that have not finished yet.
+    pub fn cancel(&self) {
+        let children = drop_temporaries!(self.children.replace(Vec::new()));
+        self.shared.ready.lock().unwrap().clear();
+        self.remaining.set(0);
+        drop(children);
+    }
+
+    /// Waits until all the children finish successfully, or until the first of them fails, in
+    /// which case the others are cancelled and its error is returned.
+    pub fn join(&self) -> Join<'_, 'a, E> {
+        Join { task_group: self }
+    }
+}
+
+pub struct Join<'g, 'a, E> {
+    task_group: &'g TaskGroup<'a, E>,
+}
+
+impl<'g, 'a, E> Future for Join<'g, 'a, E> {
+    type Output = Result<(), E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let task_group = self.task_group;
+        task_group
+            .shared
+            .waker
+            .lock()
+            .unwrap()
+            .replace(cx.waker().clone());
+        // Only the children that were ready when polling started are polled, so that a child
+        // that keeps waking itself does not starve the rest of the executor.
+        let ready = mem::take(&mut *task_group.shared.ready.lock().unwrap());
+        for index in ready {
+            let poll = match task_group.children.borrow_mut().get_mut(index) {
+                Some(Some(child)) => {
+                    child.waker.scheduled.store(false, Ordering::SeqCst);
+                    let waker = child.waker.clone().into();
+                    child.future.as_mut().poll(&mut Context::from_waker(&waker))
+                }
+                _ => continue,
+            };
+            match poll {
+                Poll::Ready(Ok(())) => {
+                    task_group.children.borrow_mut()[index] = None;
+                    task_group.remaining.set(task_group.remaining.get() - 1);
+                }
+                Poll::Ready(Err(err)) => {
+                    task_group.cancel();
+                    return Poll::Ready(Err(err));
+                }
+                Poll::Pending => {}
+            }
+        }
+        if task_group.remaining.get() == 0 {
+            task_group.cancel();
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Creates a `TaskGroup`, lets `f` spawn children into it and waits for them as
+/// `TaskGroup::join` does.
+#[allow(unused)]
+pub async fn scope<'a, E, F>(f: F) -> Result<(), E>
+where
+    F: FnOnce(&TaskGroup<'a, E>),
+{
+    let task_group = TaskGroup::new();
+    f(&task_group);
+    task_group.join().await
+}
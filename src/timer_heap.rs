@@ -0,0 +1,243 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::task::Waker;
+use std::time::Instant;
+
+pub type TimerId = u64;
+
+/// Timers ordered by deadline in a binary heap. Cancelling a timer only removes it from the map,
+/// and its heap entry is skipped once it reaches the top, so that inserting, cancelling and
+/// expiring all take amortised O(log n) time.
+pub struct TimerHeap {
+    last_id: TimerId,
+    heap: BinaryHeap<Reverse<(Instant, TimerId)>>,
+    timers: HashMap<TimerId, (Instant, Option<Waker>)>,
+}
+
+impl TimerHeap {
+    pub fn new() -> TimerHeap {
+        TimerHeap {
+            last_id: 0,
+            heap: BinaryHeap::new(),
+            timers: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    pub fn insert(&mut self, time: Instant) -> TimerId {
+        let id = self.last_id;
+        self.last_id += 1;
+        self.heap.push(Reverse((time, id)));
+        self.timers.insert(id, (time, None));
+        id
+    }
+
+    /// Moves the timer to `time`, keeping its ID and waker. A timer that has already expired
+    /// is inserted again.
+    pub fn reset(&mut self, id: TimerId, time: Instant) {
+        self.heap.push(Reverse((time, id)));
+        self.timers.entry(id).or_insert((time, None)).0 = time;
+        self.compact();
+    }
+
+    pub fn remove(&mut self, id: TimerId) {
+        self.timers.remove(&id);
+        self.compact();
+    }
+
+    /// Rebuilds the heap once stale entries make up most of it, so that frequently cancelled or
+    /// reset timers, such as per-connection timeouts, do not make it grow unbounded.
+    fn compact(&mut self) {
+        if self.heap.len() > 2 * self.timers.len() + 64 {
+            self.heap = self
+                .timers
+                .iter()
+                .map(|(&id, &(time, _))| Reverse((time, id)))
+                .collect();
+        }
+    }
+
+    /// Sets the waker to wake once the timer expires. Does nothing if it has already expired.
+    pub fn set_waker(&mut self, id: TimerId, waker: &Waker) {
+        if let Some((_, timer_waker)) = self.timers.get_mut(&id) {
+            timer_waker.replace(waker.clone());
+        }
+    }
+
+    pub fn next_time(&mut self) -> Option<Instant> {
+        while let Some(&Reverse((time, id))) = self.heap.peek() {
+            match self.timers.get(&id) {
+                Some(&(timer_time, _)) if timer_time == time => return Some(time),
+                _ => {
+                    self.heap.pop();
+                }
+            }
+        }
+        None
+    }
+
+    /// Removes the timers that are due at `now` and returns their wakers.
+    pub fn expire(&mut self, now: Instant) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        while let Some(time) = self.next_time() {
+            if time > now {
+                break;
+            }
+            let Reverse((_, id)) = self.heap.pop().unwrap();
+            if let Some((_, Some(waker))) = self.timers.remove(&id) {
+                wakers.push(waker);
+            }
+        }
+        wakers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::time::Duration;
+
+    struct CountingWaker {
+        wakes: AtomicUsize,
+    }
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.wakes.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn counting_waker() -> (Arc<CountingWaker>, Waker) {
+        let counting_waker = Arc::new(CountingWaker {
+            wakes: AtomicUsize::new(0),
+        });
+        (counting_waker.clone(), counting_waker.into())
+    }
+
+    fn wake_all(wakers: Vec<Waker>) -> usize {
+        let count = wakers.len();
+        for waker in wakers {
+            waker.wake();
+        }
+        count
+    }
+
+    #[test]
+    fn cancelled_timer_does_not_expire() {
+        let start = Instant::now();
+        let mut timers = TimerHeap::new();
+        let (cancelled_wakes, cancelled_waker) = counting_waker();
+        let (kept_wakes, kept_waker) = counting_waker();
+        let cancelled = timers.insert(start + Duration::from_millis(10));
+        let kept = timers.insert(start + Duration::from_millis(20));
+        timers.set_waker(cancelled, &cancelled_waker);
+        timers.set_waker(kept, &kept_waker);
+        timers.remove(cancelled);
+        assert_eq!(timers.next_time(), Some(start + Duration::from_millis(20)));
+        assert_eq!(
+            wake_all(timers.expire(start + Duration::from_millis(20))),
+            1
+        );
+        assert_eq!(cancelled_wakes.wakes.load(Ordering::SeqCst), 0);
+        assert_eq!(kept_wakes.wakes.load(Ordering::SeqCst), 1);
+        assert!(timers.is_empty());
+        assert_eq!(timers.next_time(), None);
+    }
+
+    #[test]
+    fn reset_earlier() {
+        let start = Instant::now();
+        let mut timers = TimerHeap::new();
+        let (wakes, waker) = counting_waker();
+        let id = timers.insert(start + Duration::from_millis(100));
+        timers.set_waker(id, &waker);
+        timers.reset(id, start + Duration::from_millis(10));
+        assert_eq!(timers.next_time(), Some(start + Duration::from_millis(10)));
+        assert_eq!(
+            wake_all(timers.expire(start + Duration::from_millis(10))),
+            1
+        );
+        // The entry for the original time is stale and skipped.
+        assert_eq!(timers.next_time(), None);
+        assert!(timers.expire(start + Duration::from_millis(100)).is_empty());
+        assert_eq!(wakes.wakes.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn reset_later() {
+        let start = Instant::now();
+        let mut timers = TimerHeap::new();
+        let (wakes, waker) = counting_waker();
+        let id = timers.insert(start + Duration::from_millis(10));
+        timers.set_waker(id, &waker);
+        timers.reset(id, start + Duration::from_millis(100));
+        assert_eq!(timers.next_time(), Some(start + Duration::from_millis(100)));
+        assert!(timers.expire(start + Duration::from_millis(50)).is_empty());
+        assert_eq!(
+            wake_all(timers.expire(start + Duration::from_millis(100))),
+            1
+        );
+        assert_eq!(wakes.wakes.load(Ordering::SeqCst), 1);
+        assert!(timers.is_empty());
+    }
+
+    #[test]
+    fn reset_after_expiry() {
+        let start = Instant::now();
+        let mut timers = TimerHeap::new();
+        let (wakes, waker) = counting_waker();
+        let id = timers.insert(start + Duration::from_millis(10));
+        timers.set_waker(id, &waker);
+        assert_eq!(
+            wake_all(timers.expire(start + Duration::from_millis(10))),
+            1
+        );
+        assert!(timers.is_empty());
+        timers.reset(id, start + Duration::from_millis(20));
+        assert!(!timers.is_empty());
+        timers.set_waker(id, &waker);
+        assert_eq!(timers.next_time(), Some(start + Duration::from_millis(20)));
+        assert_eq!(
+            wake_all(timers.expire(start + Duration::from_millis(20))),
+            1
+        );
+        assert_eq!(wakes.wakes.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn compaction_keeps_live_timers() {
+        let start = Instant::now();
+        let mut timers = TimerHeap::new();
+        let (wakes, waker) = counting_waker();
+        let live = (0..10)
+            .map(|i| timers.insert(start + Duration::from_millis(100 + i)))
+            .collect::<Vec<_>>();
+        for &id in &live {
+            timers.set_waker(id, &waker);
+        }
+        for i in 0..1000 {
+            let id = timers.insert(start + Duration::from_millis(i));
+            timers.remove(id);
+        }
+        for &id in &live {
+            timers.reset(id, start + Duration::from_millis(200));
+        }
+        assert!(timers.heap.len() <= 2 * live.len() + 64 + 1);
+        assert_eq!(timers.next_time(), Some(start + Duration::from_millis(200)));
+        assert!(timers.expire(start + Duration::from_millis(199)).is_empty());
+        assert_eq!(
+            wake_all(timers.expire(start + Duration::from_millis(200))),
+            10
+        );
+        assert_eq!(wakes.wakes.load(Ordering::SeqCst), 10);
+        assert!(timers.is_empty());
+    }
+}
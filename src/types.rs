@@ -1,11 +1,21 @@
 pub use std::os::unix::io::RawFd;
 
+use nix::errno::Errno;
 use nix::fcntl::fcntl;
 use nix::fcntl::FcntlArg;
+use nix::sys::socket::sockaddr_storage_to_addr;
+use nix::sys::socket::AddressFamily;
+use nix::sys::socket::SockAddr;
+use nix::sys::socket::SockFlag;
+use nix::sys::socket::UnixAddr;
+use nix::unistd::close;
 use std::future::Future;
 use std::io;
 use std::io::ErrorKind;
+use std::mem;
+use std::net::SocketAddr;
 use std::pin::Pin;
+use std::time::Duration;
 
 pub type BoxResult<T> = Result<T, Box<dyn std::error::Error>>;
 
@@ -20,6 +30,89 @@ pub fn dup_fd(fd: RawFd) -> BoxResult<RawFd> {
     Ok(fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0))?)
 }
 
+pub fn address_family(addr: &SocketAddr) -> AddressFamily {
+    match addr {
+        SocketAddr::V4(_) => AddressFamily::Inet,
+        SocketAddr::V6(_) => AddressFamily::Inet6,
+    }
+}
+
+/// Converts `duration` to a socket option value in seconds, checking that it is a whole number
+/// of seconds in `min..=max`, as the kernel would otherwise truncate or reject it.
+pub fn whole_seconds(name: &str, duration: Duration, min: u64, max: u64) -> BoxResult<libc::c_int> {
+    if duration.subsec_nanos() != 0 || duration.as_secs() < min || duration.as_secs() > max {
+        return Err(Box::new(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} must be a whole number of seconds between {} and {}, got {:?}",
+                name, min, max, duration
+            ),
+        )));
+    }
+    Ok(duration.as_secs() as libc::c_int)
+}
+
+/// Sets an integer socket option that `nix::sys::socket::sockopt` does not provide.
+pub fn set_int_sockopt(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> BoxResult<()> {
+    Errno::result(unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    })?;
+    Ok(())
+}
+
+/// Accepts a connection on the listening socket `fd`, returning its non-blocking socket and the
+/// address of the peer.
+pub fn accept_socket(fd: RawFd) -> nix::Result<(RawFd, SockAddr)> {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let socket = Errno::result(unsafe {
+        libc::accept4(
+            fd,
+            &mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+            &mut len,
+            (SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC).bits(),
+        )
+    })?;
+    match sockaddr_storage_to_addr(&storage, len as usize) {
+        Ok(sock_addr) => Ok((socket, sock_addr)),
+        Err(err) => {
+            close(socket)?;
+            Err(err)
+        }
+    }
+}
+
+pub fn to_socket_addr(sock_addr: SockAddr) -> BoxResult<SocketAddr> {
+    match sock_addr {
+        SockAddr::Inet(inet_addr) => Ok(inet_addr.to_std()),
+        _ => Err(Box::new(io::Error::new(
+            ErrorKind::InvalidInput,
+            "not an IP socket address",
+        ))),
+    }
+}
+
+pub fn to_unix_addr(sock_addr: SockAddr) -> BoxResult<UnixAddr> {
+    match sock_addr {
+        SockAddr::Unix(unix_addr) => Ok(unix_addr),
+        _ => Err(Box::new(io::Error::new(
+            ErrorKind::InvalidInput,
+            "not a Unix socket address",
+        ))),
+    }
+}
+
 pub trait AsyncRead {
     fn read<'a>(
         &'a mut self,
@@ -57,6 +150,15 @@ pub trait AsyncWrite {
     }
 }
 
+/// Asynchronous iterator, in the same boxed-future style as `AsyncRead` and `AsyncWrite`.
+#[allow(unused)]
+pub trait Stream {
+    type Item;
+
+    /// Waits for the next item, or returns `None` once the stream has ended.
+    fn next(&mut self) -> Box<dyn Future<Output = Option<Self::Item>> + Unpin + '_>;
+}
+
 #[macro_export]
 macro_rules! async_write {
     ($dst:expr, $($arg:tt)*) => ($dst.write_all(format!($($arg)*).as_bytes()))
@@ -0,0 +1,61 @@
+use crate::listen_socket::DEFAULT_BACKLOG;
+use crate::types::*;
+use nix::sys::socket::bind;
+use nix::sys::socket::listen;
+use nix::sys::socket::socket;
+use nix::sys::socket::AddressFamily;
+use nix::sys::socket::SockAddr;
+use nix::sys::socket::SockFlag;
+use nix::sys::socket::SockType;
+use nix::sys::socket::UnixAddr;
+use nix::unistd::close;
+use std::fs;
+use std::path::PathBuf;
+
+/// Listening Unix domain stream socket. Unlike `ListenSocket`, it is not `Clone`, as the socket
+/// file is removed when it is dropped.
+pub struct UnixListenSocket {
+    fd: RawFd,
+    path: Option<PathBuf>,
+}
+
+impl UnixListenSocket {
+    /// Binds `addr`, which is either a filesystem path, as created by `UnixAddr::new`, or a name
+    /// in the abstract namespace, as created by `UnixAddr::new_abstract`. A path must not exist
+    /// yet, so a socket file left behind by a crashed process has to be removed first.
+    pub fn bind(addr: &UnixAddr) -> BoxResult<UnixListenSocket> {
+        let mut listen_socket = UnixListenSocket {
+            fd: socket(
+                AddressFamily::Unix,
+                SockType::Stream,
+                SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+                None,
+            )?,
+            path: None,
+        };
+        bind(listen_socket.fd, &SockAddr::Unix(*addr))?;
+        listen_socket.path = addr.path().map(|path| path.to_path_buf());
+        listen(listen_socket.fd, DEFAULT_BACKLOG)?;
+        Ok(listen_socket)
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Accepts a connection, returning its non-blocking socket and the address of the peer,
+    /// which is usually unnamed.
+    pub fn accept(&mut self) -> nix::Result<(RawFd, SockAddr)> {
+        accept_socket(self.fd)
+    }
+}
+
+impl Drop for UnixListenSocket {
+    fn drop(&mut self) {
+        close(self.fd).unwrap();
+        if let Some(path) = &self.path {
+            // It may have been removed or replaced by someone else already.
+            let _ = fs::remove_file(path);
+        }
+    }
+}